@@ -0,0 +1,325 @@
+//! Discord buttons linking to the series, on the Kavita server or on sites
+//! such as AniList.
+//!
+//! `buttons.default` lists the links to try in order, and `buttons.libraries`
+//! can replace it per library. Links that aren't known for a series are
+//! skipped, and at most two buttons are shown since Discord allows no more.
+
+use log::{error, info};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::context::ReadingContext;
+use crate::kavita::{self, ExternalSeriesDto, KavitaApi};
+use crate::Config;
+
+const MAX_BUTTONS: usize = 2;
+
+/// A Kavita+ lookup that failed for another reason than there being no match
+/// is tried again after this.
+const EXTERNAL_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Link {
+    /// The series page on the Kavita server.
+    Kavita,
+    Anilist,
+    Mal,
+    Comicvine,
+}
+
+impl Link {
+    fn label(self) -> &'static str {
+        match self {
+            Link::Kavita => "View on Kavita",
+            Link::Anilist => "View on AniList",
+            Link::Mal => "View on MyAnimeList",
+            Link::Comicvine => "View on Comic Vine",
+        }
+    }
+
+    /// The host of links to this site in a series' web links.
+    fn host(self) -> Option<&'static str> {
+        match self {
+            Link::Kavita => None,
+            Link::Anilist => Some("anilist.co"),
+            Link::Mal => Some("myanimelist.net"),
+            Link::Comicvine => Some("comicvine.gamespot.com"),
+        }
+    }
+}
+
+/// `buttons` in the config.
+#[derive(Debug, Deserialize, Default)]
+pub struct ButtonConfig {
+    #[serde(default)]
+    pub default: Vec<Link>,
+    #[serde(default)]
+    pub libraries: HashMap<i32, Vec<Link>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Button {
+    pub label: String,
+    pub url: String,
+}
+
+/// Remembers what only has to be asked once: the server's base URL and each
+/// series' Kavita+ match.
+#[derive(Debug, Default)]
+pub struct LinkResolver {
+    base_path: Option<String>,
+    /// Kavita+ matches by series id, `None` when the server has none.
+    external: HashMap<i32, Option<ExternalSeriesDto>>,
+    /// When the Kavita+ lookup last failed, by series id.
+    failed: HashMap<i32, Instant>,
+}
+
+impl LinkResolver {
+    /// The buttons configured for the library of `context`, in order.
+    pub async fn buttons(&mut self, api: &impl KavitaApi, config: &Config, context: &ReadingContext) -> Vec<Button> {
+        let Some(buttons) = &config.buttons else {
+            return Vec::new();
+        };
+        let links = buttons.libraries.get(&context.library_id).unwrap_or(&buttons.default);
+
+        let mut found = Vec::new();
+        for link in links {
+            if found.len() == MAX_BUTTONS {
+                break;
+            }
+            let url = match link {
+                Link::Kavita => self.series_page(api, config, context).await,
+                _ => self.external_page(api, context, *link).await,
+            };
+            if let Some(url) = url.filter(|url| url.starts_with("https://") || url.starts_with("http://")) {
+                found.push(Button { label: link.label().to_string(), url });
+            }
+        }
+        found
+    }
+
+    async fn series_page(&mut self, api: &impl KavitaApi, config: &Config, context: &ReadingContext) -> Option<String> {
+        if self.base_path.is_none() {
+            match api.server_base_url().await {
+                Ok(base) => self.base_path = Some(base.trim_matches('/').to_string()),
+                Err(e) => {
+                    error!("Failed to get the server's base URL: {}", e);
+                    return None;
+                }
+            }
+        }
+        let base_path = self.base_path.as_deref().unwrap_or_default();
+
+        // kavita_url may already point below the base URL, e.g. https://example.com/kavita.
+        let mut url = Url::parse(&config.kavita_url).ok()?;
+        let mut path = url.path().trim_end_matches('/').to_string();
+        if !base_path.is_empty() && !path.ends_with(&format!("/{}", base_path)) {
+            path = format!("{}/{}", path, base_path);
+        }
+        url.set_path(&format!("{}/library/{}/series/{}", path, context.library_id, context.series_id));
+        url.set_query(None);
+        Some(url.to_string())
+    }
+
+    async fn external_page(&mut self, api: &impl KavitaApi, context: &ReadingContext, link: Link) -> Option<String> {
+        let host = link.host()?;
+        let web_link = context.metadata.as_ref()
+            .and_then(|m| m.webLinks.as_deref())
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .find(|url| is_on(url, host));
+        if let Some(url) = web_link {
+            return Some(url.to_string());
+        }
+
+        if !matches!(link, Link::Anilist | Link::Mal) {
+            return None;
+        }
+        let external = self.external_series(api, context.series_id).await?;
+
+        match link {
+            Link::Anilist => external.aniListId.filter(|id| *id > 0)
+                .map(|id| format!("https://anilist.co/manga/{}", id)),
+            Link::Mal => external.malId.filter(|id| *id > 0)
+                .map(|id| format!("https://myanimelist.net/manga/{}", id)),
+            _ => None,
+        }
+    }
+
+    /// The series' Kavita+ match. Only answers from the server are remembered;
+    /// other failures are retried after [`EXTERNAL_RETRY_DELAY`].
+    async fn external_series(&mut self, api: &impl KavitaApi, series_id: i32) -> Option<ExternalSeriesDto> {
+        if let Some(external) = self.external.get(&series_id) {
+            return external.clone();
+        }
+        if self.failed.get(&series_id).is_some_and(|at| at.elapsed() < EXTERNAL_RETRY_DELAY) {
+            return None;
+        }
+
+        match api.external_series(series_id).await {
+            Ok(external) => {
+                self.failed.remove(&series_id);
+                self.external.insert(series_id, Some(external.clone()));
+                Some(external)
+            },
+            Err(e) if kavita::is_not_found(e.as_ref()) => {
+                info!("No Kavita+ match for series {}", series_id);
+                self.failed.remove(&series_id);
+                self.external.insert(series_id, None);
+                None
+            },
+            Err(e) => {
+                error!("Failed to get the Kavita+ match for series {}: {}", series_id, e);
+                self.failed.insert(series_id, Instant::now());
+                None
+            }
+        }
+    }
+}
+
+fn is_on(url: &str, host: &str) -> bool {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(|h| h.trim_start_matches("www.") == host))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kavita::fake::FakeKavita;
+    use crate::test_config;
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    fn with_buttons(buttons: serde_json::Value) -> Config {
+        test_config(json!({ "buttons": buttons }))
+    }
+
+    fn anilist_buttons() -> Config {
+        with_buttons(json!({ "default": ["anilist"] }))
+    }
+
+    fn matched() -> ExternalSeriesDto {
+        ExternalSeriesDto { aniListId: Some(30013), malId: None }
+    }
+
+    fn urls(buttons: &[Button]) -> Vec<&str> {
+        buttons.iter().map(|button| button.url.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn buttons_are_off_without_a_buttons_section() {
+        let api = FakeKavita::default();
+        let config = test_config(json!({}));
+        assert!(LinkResolver::default().buttons(&api, &config, &ReadingContext::sample()).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn buttons_link_the_series_page_under_the_base_url() {
+        let config = with_buttons(json!({ "default": ["kavita"] }));
+        let context = ReadingContext::sample();
+
+        let api = FakeKavita::default();
+        let buttons = LinkResolver::default().buttons(&api, &config, &context).await;
+        assert_eq!(urls(&buttons), ["https://kavita.example.com/library/1/series/1"]);
+
+        let api = FakeKavita { base_url: "/kavita/".to_string(), ..Default::default() };
+        let buttons = LinkResolver::default().buttons(&api, &config, &context).await;
+        assert_eq!(urls(&buttons), ["https://kavita.example.com/kavita/library/1/series/1"]);
+
+        let config = test_config(json!({
+            "kavita_url": "https://kavita.example.com/kavita/",
+            "buttons": { "default": ["kavita"] },
+        }));
+        let buttons = LinkResolver::default().buttons(&api, &config, &context).await;
+        let url = "https://kavita.example.com/kavita/library/1/series/1";
+        assert_eq!(urls(&buttons), [url], "the base URL is already in kavita_url");
+    }
+
+    #[tokio::test]
+    async fn buttons_prefer_the_series_web_links() {
+        let mut api = FakeKavita::default();
+        api.external_series.insert(1, matched());
+        let web_links = [
+            "https://example.com/x",
+            "https://www.anilist.co/manga/1",
+            "https://comicvine.gamespot.com/x",
+        ].join(", ");
+        let context = ReadingContext {
+            metadata: Some(serde_json::from_value(json!({ "webLinks": web_links })).unwrap()),
+            ..ReadingContext::sample()
+        };
+
+        let config = with_buttons(json!({ "default": ["comicvine", "anilist"] }));
+        let buttons = LinkResolver::default().buttons(&api, &config, &context).await;
+        assert_eq!(urls(&buttons), ["https://comicvine.gamespot.com/x", "https://www.anilist.co/manga/1"]);
+        assert_eq!(buttons[0].label, "View on Comic Vine");
+    }
+
+    #[tokio::test]
+    async fn buttons_follow_the_library_and_stop_at_two() {
+        let mut api = FakeKavita::default();
+        api.external_series.insert(1, ExternalSeriesDto { aniListId: Some(30013), malId: Some(2) });
+        let config = with_buttons(json!({
+            "default": ["kavita"],
+            "libraries": { "1": ["comicvine", "mal", "anilist", "kavita"] },
+        }));
+
+        let buttons = LinkResolver::default().buttons(&api, &config, &ReadingContext::sample()).await;
+        assert_eq!(urls(&buttons), ["https://myanimelist.net/manga/2", "https://anilist.co/manga/30013"]);
+
+        let other_library = ReadingContext { library_id: 2, ..ReadingContext::sample() };
+        let buttons = LinkResolver::default().buttons(&api, &config, &other_library).await;
+        assert_eq!(buttons.len(), 1);
+        assert_eq!(buttons[0].label, "View on Kavita");
+    }
+
+    #[tokio::test]
+    async fn buttons_link_the_kavita_plus_match() {
+        let mut api = FakeKavita::default();
+        api.external_series.insert(1, matched());
+
+        let context = ReadingContext::sample();
+        let buttons = LinkResolver::default().buttons(&api, &anilist_buttons(), &context).await;
+        assert_eq!(buttons, [Button {
+            label: "View on AniList".to_string(),
+            url: "https://anilist.co/manga/30013".to_string(),
+        }]);
+    }
+
+    #[tokio::test]
+    async fn buttons_remember_that_a_series_has_no_match() {
+        let (config, context) = (anilist_buttons(), ReadingContext::sample());
+        let mut resolver = LinkResolver::default();
+        let mut api = FakeKavita::default();
+        api.errors.insert("external_series", StatusCode::NOT_FOUND);
+        assert!(resolver.buttons(&api, &config, &context).await.is_empty());
+
+        api.errors.clear();
+        api.external_series.insert(1, matched());
+        assert!(resolver.buttons(&api, &config, &context).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn buttons_retry_a_failed_lookup_later() {
+        let (config, context) = (anilist_buttons(), ReadingContext::sample());
+        let mut resolver = LinkResolver::default();
+        let mut api = FakeKavita::default();
+        api.errors.insert("external_series", StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(resolver.buttons(&api, &config, &context).await.is_empty());
+
+        api.errors.clear();
+        api.external_series.insert(1, matched());
+        assert!(resolver.buttons(&api, &config, &context).await.is_empty(), "too soon to retry");
+
+        let long_ago = Instant::now().checked_sub(EXTERNAL_RETRY_DELAY).unwrap();
+        resolver.failed.insert(context.series_id, long_ago);
+        assert_eq!(resolver.buttons(&api, &config, &context).await.len(), 1);
+    }
+}
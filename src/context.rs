@@ -0,0 +1,1037 @@
+use log::{error, info, warn};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::kavita::{
+    ApiResult, BookChapterItem, ChapterDto, ChapterInfoDto, ChapterMetadataDto, KavitaApi, LibraryType,
+    MangaFormat, ProgressDto, SeriesDetailDto, SeriesDto, SeriesMetadataDto, VolumeDto,
+};
+use crate::presence::{self, TimestampMode};
+use crate::reading_list::ListPosition;
+use crate::speed::SpeedModel;
+use crate::{credits, get_cover_url, Config};
+
+/// How a position in the series is described.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadingKind {
+    /// Volumes and chapters.
+    Manga,
+    /// Issues.
+    Comic,
+    /// A title and how far into it the reader is.
+    Book,
+}
+
+impl ReadingKind {
+    /// EPUB and PDF files always read as books; otherwise the library type decides.
+    fn from_library(library_type: LibraryType, format: MangaFormat) -> Option<Self> {
+        match (format, library_type) {
+            (MangaFormat::Epub | MangaFormat::Pdf, _) => Some(ReadingKind::Book),
+            (_, LibraryType::Book | LibraryType::LightNovel) => Some(ReadingKind::Book),
+            (_, LibraryType::Comic | LibraryType::ComicVine) => Some(ReadingKind::Comic),
+            (_, LibraryType::Manga | LibraryType::Image) => Some(ReadingKind::Manga),
+            (_, LibraryType::Unknown) => None,
+        }
+    }
+}
+
+/// Everything known about what is being read, gathered from Kavita before any
+/// presence text is produced.
+#[derive(Debug, Clone)]
+pub struct ReadingContext {
+    pub series_id: i32,
+    pub series_name: String,
+    pub library_id: i32,
+    pub library_name: Option<String>,
+    pub library_type: LibraryType,
+    pub format: MangaFormat,
+    pub chapter_id: i32,
+    /// Kavita's chapter number; `-100000` marks a loose-leaf volume or a book.
+    pub chapter_number: String,
+    pub chapter_range: String,
+    pub chapter_title: Option<String>,
+    pub is_special: bool,
+    /// `None` for books, specials and chapters that aren't in a volume.
+    pub volume_number: Option<String>,
+    pub kind: ReadingKind,
+    pub page: i32,
+    pub pages: i32,
+    /// The EPUB table of contents entry containing the current page, after the
+    /// entries it is nested in, e.g. `["Part 2", "Chapter 7: The Storm"]`.
+    pub section: Vec<String>,
+    /// Kavita's estimate for reading the whole chapter, from its word or page count.
+    pub chapter_hours: Option<f64>,
+    /// Kavita's estimate for finishing the series from the current progress.
+    /// Only fetched when the timestamps need it.
+    pub series_hours_left: Option<f64>,
+    /// Total and read page counts across the whole series, 0 when unknown.
+    pub series_pages: i32,
+    pub series_pages_read: i32,
+    /// 1-based place of the chapter among the series' chapters, specials left
+    /// out, and how many there are. `None` and 0 without the series detail.
+    pub series_chapter: Option<usize>,
+    pub series_chapters: usize,
+    /// From the credits, see [`credits::author`].
+    pub author: Option<String>,
+    pub metadata: Option<SeriesMetadataDto>,
+    /// Set by the caller when the chapter is being read through a reading list.
+    pub reading_list: Option<ListPosition>,
+    pub series_cover_url: Option<String>,
+    pub chapter_cover_url: Option<String>,
+}
+
+impl ReadingContext {
+    /// True when the progress is for a whole volume rather than a numbered chapter.
+    pub fn is_loose_leaf(&self) -> bool {
+        self.chapter_number.contains("-100000")
+    }
+
+    pub fn is_book(&self) -> bool {
+        self.kind == ReadingKind::Book
+    }
+
+    /// The chapter or issue number as Kavita has it, keeping decimals like
+    /// `10.5`. `None` for books, specials and loose-leaf volumes.
+    pub fn chapter_label(&self) -> Option<String> {
+        if self.is_book() || self.is_special || self.is_loose_leaf() {
+            return None;
+        }
+
+        [&self.chapter_range, &self.chapter_number]
+            .into_iter()
+            .find(|label| !label.is_empty() && label.chars().any(|c| c.is_ascii_digit()))
+            .cloned()
+    }
+}
+
+/// Placeholders that need the series' chapter list from `/api/Series/series-detail`.
+const SERIES_POSITION_PLACEHOLDERS: &[&str] = &["series_chapter", "series_chapters", "series_progress"];
+
+/// Series lookups are fetched again after this, to pick up new chapters and
+/// metadata edits.
+const SERIES_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Lookups kept across polls: chapter lookups for good, series lookups until
+/// they are [`SERIES_REFRESH_INTERVAL`] old.
+#[derive(Debug, Default)]
+pub struct ResolveCache {
+    /// `/api/Chapter` by chapter id.
+    chapters: HashMap<i32, ChapterDto>,
+    /// `/api/Series/chapter-metadata` by chapter id.
+    chapter_metadata: HashMap<i32, ChapterMetadataDto>,
+    /// EPUB tables of contents by chapter id.
+    tocs: HashMap<i32, Vec<BookChapterItem>>,
+    /// `/api/Series/{id}` by series id, with when it was fetched.
+    series: HashMap<i32, (SeriesDto, Instant)>,
+    /// `/api/Series/metadata` by series id, with when it was fetched.
+    metadata: HashMap<i32, (SeriesMetadataDto, Instant)>,
+    /// `/api/Series/series-detail` by series id, with when it was fetched.
+    details: HashMap<i32, (SeriesDetailDto, Instant)>,
+}
+
+impl ResolveCache {
+    /// `/api/Chapter`, from the cache after the first successful lookup.
+    async fn chapter(&mut self, api: &impl KavitaApi, chapter_id: i32) -> Option<ChapterDto> {
+        if let Some(chapter) = self.chapters.get(&chapter_id) {
+            return Some(chapter.clone());
+        }
+
+        match api.chapter(chapter_id).await {
+            Ok(chapter) => {
+                self.chapters.insert(chapter_id, chapter.clone());
+                Some(chapter)
+            },
+            Err(e) => {
+                error!("Failed to get chapter details: {}", e);
+                None
+            }
+        }
+    }
+
+    /// `/api/Series/chapter-metadata`, from the cache after the first successful lookup.
+    async fn chapter_metadata(&mut self, api: &impl KavitaApi, chapter_id: i32) -> Option<ChapterMetadataDto> {
+        if let Some(metadata) = self.chapter_metadata.get(&chapter_id) {
+            return Some(metadata.clone());
+        }
+
+        match api.chapter_metadata(chapter_id).await {
+            Ok(metadata) => {
+                self.chapter_metadata.insert(chapter_id, metadata.clone());
+                Some(metadata)
+            },
+            Err(e) => {
+                error!("Failed to get chapter metadata: {}", e);
+                None
+            }
+        }
+    }
+
+    /// `/api/Series/{id}`, from the cache while it is fresh.
+    async fn series(&mut self, api: &impl KavitaApi, series_id: i32) -> Option<SeriesDto> {
+        refreshed(&mut self.series, series_id, api.series(series_id), "series").await
+    }
+
+    /// `/api/Series/metadata`, from the cache while it is fresh.
+    async fn metadata(&mut self, api: &impl KavitaApi, series_id: i32) -> Option<SeriesMetadataDto> {
+        refreshed(&mut self.metadata, series_id, api.series_metadata(series_id), "series metadata").await
+    }
+
+    /// `/api/Series/series-detail`, from the cache while it is fresh.
+    async fn detail(&mut self, api: &impl KavitaApi, series_id: i32) -> Option<SeriesDetailDto> {
+        refreshed(&mut self.details, series_id, api.series_detail(series_id), "series details").await
+    }
+
+    /// `/api/Book/{chapterId}/chapters`, from the cache after the first successful lookup.
+    async fn toc(&mut self, api: &impl KavitaApi, chapter_id: i32) -> Option<&[BookChapterItem]> {
+        if let Entry::Vacant(slot) = self.tocs.entry(chapter_id) {
+            match api.book_chapters(chapter_id).await {
+                Ok(toc) => {
+                    slot.insert(toc);
+                },
+                Err(e) => {
+                    error!("Failed to get the table of contents: {}", e);
+                    return None;
+                }
+            }
+        }
+        self.tocs.get(&chapter_id).map(Vec::as_slice)
+    }
+}
+
+/// The cached entry for `series_id` while it is younger than
+/// [`SERIES_REFRESH_INTERVAL`], else the result of `fetch`. A stale entry is
+/// kept when the refresh fails.
+async fn refreshed<T: Clone>(
+    cache: &mut HashMap<i32, (T, Instant)>,
+    series_id: i32,
+    fetch: impl Future<Output = ApiResult<T>>,
+    what: &str,
+) -> Option<T> {
+    if let Some((value, fetched_at)) = cache.get(&series_id) {
+        if fetched_at.elapsed() < SERIES_REFRESH_INTERVAL {
+            return Some(value.clone());
+        }
+    }
+
+    match fetch.await {
+        Ok(value) => {
+            cache.insert(series_id, (value.clone(), Instant::now()));
+            Some(value)
+        },
+        Err(e) => {
+            error!("Failed to get {}: {}", what, e);
+            cache.get(&series_id).map(|(value, _)| value.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+impl ReadingContext {
+    /// Chapter 21 of a manga series, with nothing optional filled in.
+    pub fn sample() -> Self {
+        ReadingContext {
+            series_id: 1,
+            series_name: "Series".to_string(),
+            library_id: 1,
+            library_name: None,
+            library_type: LibraryType::Manga,
+            format: MangaFormat::Archive,
+            chapter_id: 10,
+            chapter_number: "21".to_string(),
+            chapter_range: "21".to_string(),
+            chapter_title: None,
+            is_special: false,
+            volume_number: None,
+            kind: ReadingKind::Manga,
+            page: 5,
+            pages: 20,
+            section: Vec::new(),
+            chapter_hours: None,
+            series_hours_left: None,
+            series_pages: 0,
+            series_pages_read: 0,
+            series_chapter: None,
+            series_chapters: 0,
+            author: None,
+            metadata: None,
+            reading_list: None,
+            series_cover_url: None,
+            chapter_cover_url: None,
+        }
+    }
+}
+
+/// What is known about the chapter itself, from whichever endpoints the server has.
+struct ChapterFacts {
+    chapter_number: String,
+    chapter_range: String,
+    chapter_title: Option<String>,
+    is_special: bool,
+    volume_number: Option<String>,
+    pages: i32,
+    library_type: LibraryType,
+    format: Option<MangaFormat>,
+    series_name: Option<String>,
+    /// Total and read pages of the series, when the endpoint reports them.
+    series_pages: Option<(i32, i32)>,
+    /// Used to tell books apart when the library type is unknown.
+    in_volume: bool,
+    /// `/api/Chapter`, for the file path, reading time and cover. Only looked
+    /// up on servers with chapter-info when one of those is needed.
+    chapter: Option<ChapterDto>,
+}
+
+/// Resolves the chapter, series and metadata behind a progress entry.
+/// Returns `None` when the chapter can't be found at all.
+pub async fn resolve(
+    api: &impl KavitaApi,
+    config: &Config,
+    cache: &mut ResolveCache,
+    speed: &SpeedModel,
+    progress: &ProgressDto,
+    series_id: i32,
+    series_name: &str,
+) -> Option<ReadingContext> {
+    let mut facts = match api.chapter_info(progress.chapterId).await {
+        Ok(info) => from_chapter_info(cache, progress, info),
+        Err(e) => {
+            warn!("Failed to get chapter info ({}), using the lookups for older servers", e);
+            let detail = cache.detail(api, series_id).await;
+            legacy_facts(api, cache, progress, detail.as_ref()).await?
+        }
+    };
+
+    let series = cache.series(api, series_id).await;
+    let (series_pages, series_pages_read) = match (&series, facts.series_pages) {
+        (_, Some(pages)) => pages,
+        (Some(series), None) => (series.pages, series.pagesRead),
+        (None, None) => (0, 0),
+    };
+    let format = match (&series, facts.format) {
+        (_, Some(format)) => format,
+        (Some(series), None) => series.format,
+        (None, None) => MangaFormat::Unknown,
+    };
+    let (name, library_name, series_cover) = match series {
+        Some(series) => (series.name, series.libraryName, series.coverImage),
+        None => {
+            let name = match &facts.series_name {
+                Some(name) if !name.is_empty() => name.clone(),
+                _ if !series_name.is_empty() => series_name.to_string(),
+                _ => format!("Series {}", series_id),
+            };
+            (name, None, None)
+        }
+    };
+
+    let metadata = cache.metadata(api, series_id).await;
+
+    let series_hours_left = if config.timestamp_mode.unwrap_or_default() == TimestampMode::SeriesRemaining {
+        match api.time_left(series_id).await {
+            Ok(estimate) => Some(estimate.avgHours).filter(|hours| *hours > 0.0),
+            Err(e) => {
+                error!("Failed to get time left for series: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    if facts.chapter.is_none() && needs_chapter(config, speed, &facts, series_cover.as_deref()) {
+        facts.chapter = cache.chapter(api, progress.chapterId).await;
+    }
+
+    let chapter_metadata = cache.chapter_metadata(api, progress.chapterId).await;
+
+    let file_path = facts.chapter.as_ref()
+        .and_then(|chapter| chapter.files.as_ref())
+        .and_then(|files| files.first())
+        .map(|file| file.filePath.as_str());
+    let author = credits::author(config, chapter_metadata.as_ref(), metadata.as_ref(), file_path);
+
+    let kind = match ReadingKind::from_library(facts.library_type, format) {
+        Some(kind) => kind,
+        None if facts.chapter_number.contains("-100000") && !facts.in_volume => ReadingKind::Book,
+        None => ReadingKind::Manga,
+    };
+
+    let volume_number = facts.volume_number.filter(|_| kind != ReadingKind::Book && !facts.is_special);
+
+    // EPUB pages are spine entries, so the table of contents says more about where the reader is.
+    let section = if format == MangaFormat::Epub {
+        cache.toc(api, progress.chapterId).await
+            .map(|toc| toc_path(toc, progress.pageNum))
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    let chapter_order = if presence::uses_placeholder(config, SERIES_POSITION_PLACEHOLDERS) {
+        cache.detail(api, series_id).await.as_ref().map(chapter_order).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    let image_format = config.image_format.as_deref().unwrap_or("png");
+    let proxy_enabled = config.proxy_enabled.unwrap_or(true);
+
+    let series_cover_url = series_cover.filter(|c| !c.is_empty()).map(|_| {
+        let params = format!("?seriesId={}&apiKey={}&format={}",
+            series_id, config.kavita_api_key, image_format);
+        get_cover_url(&config.kavita_url, "/api/Image/series-cover", &params, proxy_enabled)
+    });
+
+    let chapter_cover_url = facts.chapter.as_ref()
+        .and_then(|chapter| chapter.coverImage.as_ref())
+        .filter(|c| !c.is_empty())
+        .map(|_| {
+            let params = format!("?chapterId={}&apiKey={}&format={}",
+                progress.chapterId, config.kavita_api_key, image_format);
+            get_cover_url(&config.kavita_url, "/api/Image/chapter-cover", &params, proxy_enabled)
+        });
+
+    Some(ReadingContext {
+        series_id,
+        series_name: name,
+        library_id: progress.libraryId,
+        library_name,
+        library_type: facts.library_type,
+        format,
+        chapter_id: progress.chapterId,
+        chapter_number: facts.chapter_number,
+        chapter_range: facts.chapter_range,
+        chapter_title: facts.chapter_title,
+        is_special: facts.is_special,
+        volume_number,
+        kind,
+        page: progress.pageNum,
+        pages: facts.pages,
+        section,
+        chapter_hours: facts.chapter.as_ref()
+            .map(|chapter| chapter.avgHoursToRead)
+            .filter(|hours| *hours > 0.0),
+        series_hours_left,
+        series_pages,
+        series_pages_read,
+        series_chapter: chapter_order.iter().position(|id| *id == progress.chapterId).map(|index| index + 1),
+        series_chapters: chapter_order.len(),
+        author,
+        metadata,
+        reading_list: None,
+        series_cover_url,
+        chapter_cover_url,
+    })
+}
+
+/// Everything about the chapter comes from `/api/Reader/chapter-info`.
+/// `/api/Chapter` is only used here when it is already cached.
+fn from_chapter_info(cache: &ResolveCache, progress: &ProgressDto, info: ChapterInfoDto) -> ChapterFacts {
+    let chapter = cache.chapters.get(&progress.chapterId).cloned();
+
+    ChapterFacts {
+        chapter_range: chapter.as_ref()
+            .map(|chapter| chapter.range.clone())
+            .unwrap_or_else(|| info.chapterNumber.clone()),
+        chapter_number: info.chapterNumber,
+        chapter_title: info.chapterTitle.filter(|title| !title.is_empty()),
+        is_special: info.isSpecial,
+        volume_number: volume_label(&info.volumeNumber),
+        pages: info.pages,
+        library_type: info.libraryType,
+        format: Some(info.seriesFormat),
+        series_name: Some(info.seriesName),
+        series_pages: Some((info.seriesTotalPages, info.seriesTotalPagesRead)),
+        in_volume: volume_label(&info.volumeNumber).is_some(),
+        chapter,
+    }
+}
+
+/// Whether `/api/Chapter` has anything that will be shown: the file path for
+/// `author_from_path`, the chapter cover when the series has none, or Kavita's
+/// reading time estimate while no reading speed has been learned.
+fn needs_chapter(
+    config: &Config,
+    speed: &SpeedModel,
+    facts: &ChapterFacts,
+    series_cover: Option<&str>,
+) -> bool {
+    let counts_down = config.timestamp_mode.unwrap_or_default() == TimestampMode::ChapterRemaining
+        || presence::uses_placeholder(config, &["time_left"]);
+
+    config.author_from_path.unwrap_or(false)
+        || series_cover.is_none_or(str::is_empty)
+        || (counts_down && speed.pages_per_minute(facts.library_type).is_none())
+}
+
+/// For servers without `/api/Reader/chapter-info`: the chapter (or book info
+/// for books), the volume from the series detail, and the library type.
+async fn legacy_facts(
+    api: &impl KavitaApi,
+    cache: &mut ResolveCache,
+    progress: &ProgressDto,
+    detail: Option<&SeriesDetailDto>,
+) -> Option<ChapterFacts> {
+    let chapter: ChapterDto = match cache.chapter(api, progress.chapterId).await {
+        Some(ch) => ch,
+        None => {
+            match api.book_info(progress.chapterId).await {
+                Ok(book_info) => ChapterDto {
+                    id: progress.chapterId,
+                    range: book_info.seriesName.clone(),
+                    title: book_info.chapterTitle.clone(),
+                    pages: book_info.pages,
+                    coverImage: None,
+                    volumeId: book_info.volumeId,
+                    chapterNumber: book_info.chapterNumber.clone(),
+                    files: None,
+                    avgHoursToRead: 0.0,
+                    isSpecial: false,
+                    sortOrder: 0.0,
+                },
+                Err(e) => {
+                    error!("Failed to get book info: {}", e);
+                    return None;
+                }
+            }
+        }
+    };
+
+    let library_type = match api.library_type(progress.libraryId).await {
+        Ok(library_type) => library_type,
+        Err(e) => {
+            error!("Failed to get library type: {}", e);
+            LibraryType::Unknown
+        }
+    };
+
+    let volume = detail
+        .filter(|_| chapter.volumeId > 0)
+        .and_then(|detail| detail.volumes.iter().find(|vol| vol.id == chapter.volumeId));
+    if let Some(vol) = volume {
+        info!("Found matching volume in detail: id={}, name={:?}, number={}",
+             vol.id, vol.name, vol.number);
+    }
+
+    Some(ChapterFacts {
+        chapter_number: chapter.chapterNumber.clone(),
+        chapter_range: chapter.range.clone(),
+        chapter_title: chapter.title.clone(),
+        is_special: chapter.isSpecial,
+        volume_number: volume.and_then(|vol| volume_label(&vol.number.to_string())),
+        pages: chapter.pages,
+        library_type,
+        format: None,
+        series_name: None,
+        series_pages: None,
+        in_volume: volume.is_some(),
+        chapter: Some(chapter),
+    })
+}
+
+/// Chapter ids in reading order: volumes by number, then the chapters that
+/// aren't in a volume yet. Specials are left out.
+fn chapter_order(detail: &SeriesDetailDto) -> Vec<i32> {
+    let mut volumes: Vec<&VolumeDto> = detail.volumes.iter().collect();
+    volumes.sort_by_key(|vol| vol.number);
+
+    let mut order = Vec::new();
+    for chapters in volumes.iter().map(|vol| &vol.chapters).chain([&detail.storylineChapters]) {
+        let mut chapters: Vec<&ChapterDto> = chapters.iter().collect();
+        chapters.sort_by(|a, b| a.sortOrder.total_cmp(&b.sortOrder));
+        for chapter in chapters {
+            if !chapter.isSpecial && !order.contains(&chapter.id) {
+                order.push(chapter.id);
+            }
+        }
+    }
+    order
+}
+
+/// Titles from the outermost table of contents entry down to the innermost
+/// one that starts at or before `page`. At each level the entry starting
+/// closest before `page` is picked, whatever order Kavita lists them in.
+fn toc_path(items: &[BookChapterItem], page: i32) -> Vec<String> {
+    let Some(item) = items.iter().filter(|item| item.page <= page).max_by_key(|item| item.page) else {
+        return Vec::new();
+    };
+
+    let mut path = vec![item.title.trim().to_string()];
+    path.extend(toc_path(&item.children, page));
+    path.retain(|title| !title.is_empty());
+    path
+}
+
+/// The volume number to show. Kavita files loose chapters under volume
+/// -100000 and specials under 100000, which aren't real volumes.
+fn volume_label(number: &str) -> Option<String> {
+    let number = number.trim();
+    match number.parse::<f64>() {
+        Ok(value) if value.abs() != 100000.0 => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kavita::fake::FakeKavita;
+    use crate::kavita::{BookInfoDto, ChapterInfoDto, FileDto, HourEstimateRangeDto, VolumeDto};
+    use crate::test_config;
+    use serde_json::json;
+
+    const PROGRESS: ProgressDto = ProgressDto { chapterId: 10, pageNum: 5, libraryId: 1 };
+
+    /// Series 1 with a cover. The chapter lookups are left to each test.
+    fn fake() -> FakeKavita {
+        let mut api = FakeKavita::default();
+        api.series.insert(1, serde_json::from_value(json!({
+            "name": "Series",
+            "coverImage": "cover.png",
+        })).unwrap());
+        api
+    }
+
+    fn chapter_info() -> ChapterInfoDto {
+        serde_json::from_value(json!({
+            "chapterNumber": "21",
+            "volumeNumber": "3",
+            "seriesName": "Series",
+            "seriesFormat": 1,
+            "libraryType": 0,
+            "chapterTitle": "The Storm",
+            "pages": 20,
+            "seriesTotalPages": 400,
+            "seriesTotalPagesRead": 120,
+        })).unwrap()
+    }
+
+    fn chapter(id: i32, sort_order: f32) -> ChapterDto {
+        ChapterDto {
+            id,
+            range: sort_order.to_string(),
+            title: None,
+            pages: 20,
+            coverImage: None,
+            volumeId: 0,
+            chapterNumber: sort_order.to_string(),
+            files: None,
+            avgHoursToRead: 0.0,
+            isSpecial: false,
+            sortOrder: sort_order,
+        }
+    }
+
+    /// Chapter 21 "The Storm", in volume 3.
+    fn the_storm() -> ChapterDto {
+        ChapterDto {
+            title: Some("The Storm".to_string()),
+            volumeId: 7,
+            files: Some(vec![FileDto { filePath: "/manga/Author/Series/21.cbz".to_string() }]),
+            avgHoursToRead: 0.5,
+            ..chapter(10, 21.0)
+        }
+    }
+
+    fn special(id: i32) -> ChapterDto {
+        ChapterDto { isSpecial: true, ..chapter(id, 0.0) }
+    }
+
+    fn volume(number: i32, chapters: Vec<ChapterDto>) -> VolumeDto {
+        VolumeDto { id: number, number, name: None, chapters }
+    }
+
+    fn entry(title: &str, page: i32, children: Vec<BookChapterItem>) -> BookChapterItem {
+        BookChapterItem { title: title.to_string(), page, children }
+    }
+
+    fn book() -> Vec<BookChapterItem> {
+        vec![
+            entry("Part 1", 2, vec![entry("Chapter 1", 2, vec![]), entry("Chapter 2", 10, vec![])]),
+            entry("Part 2", 20, vec![
+                entry("Chapter 7: The Storm", 24, vec![]),
+                entry("Chapter 8", 31, vec![]),
+            ]),
+        ]
+    }
+
+    async fn resolve_with(api: &FakeKavita, config: &Config) -> Option<ReadingContext> {
+        let mut cache = ResolveCache::default();
+        resolve(api, config, &mut cache, &SpeedModel::default(), &PROGRESS, 1, "History name").await
+    }
+
+    #[tokio::test]
+    async fn resolve_uses_chapter_info() {
+        let mut api = fake();
+        api.chapter_infos.insert(10, chapter_info());
+        api.errors.insert("series_detail", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+
+        let context = resolve_with(&api, &test_config(json!({}))).await.unwrap();
+        assert_eq!(context.series_name, "Series");
+        assert_eq!((context.chapter_number.as_str(), context.chapter_range.as_str()), ("21", "21"));
+        assert_eq!(context.chapter_title.as_deref(), Some("The Storm"));
+        assert_eq!(context.volume_number.as_deref(), Some("3"));
+        assert_eq!(context.kind, ReadingKind::Manga);
+        assert_eq!((context.page, context.pages), (5, 20));
+        assert_eq!((context.series_pages, context.series_pages_read), (400, 120));
+        assert_eq!(context.author, None, "nobody is credited");
+    }
+
+    #[tokio::test]
+    async fn resolve_takes_the_file_path_and_reading_time_from_the_chapter() {
+        let mut api = fake();
+        api.chapter_infos.insert(10, chapter_info());
+        api.chapters.insert(10, the_storm());
+
+        let config = test_config(json!({ "author_from_path": true }));
+        let context = resolve_with(&api, &config).await.unwrap();
+        assert_eq!(context.author.as_deref(), Some("Author"));
+        assert_eq!(context.chapter_hours, Some(0.5));
+    }
+
+    #[tokio::test]
+    async fn resolve_takes_the_author_from_the_credits() {
+        let mut api = fake();
+        api.chapter_infos.insert(10, chapter_info());
+        let series_metadata = json!({ "writers": [{ "name": "Writer" }] });
+        api.series_metadata.insert(1, serde_json::from_value(series_metadata).unwrap());
+        let chapter_metadata = json!({ "writers": [{ "name": "Guest Writer" }] });
+        api.chapter_metadata.insert(10, serde_json::from_value(chapter_metadata).unwrap());
+
+        let context = resolve_with(&api, &test_config(json!({}))).await.unwrap();
+        assert_eq!(context.author.as_deref(), Some("Guest Writer"));
+
+        api.errors.insert("chapter_metadata", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        let context = resolve_with(&api, &test_config(json!({}))).await.unwrap();
+        assert_eq!(context.author.as_deref(), Some("Writer"));
+    }
+
+    #[tokio::test]
+    async fn resolve_falls_back_to_the_chapter_and_series_detail() {
+        let mut api = fake();
+        api.errors.insert("chapter_info", reqwest::StatusCode::NOT_FOUND);
+        api.chapters.insert(10, the_storm());
+        api.series_details.insert(1, SeriesDetailDto {
+            volumes: vec![VolumeDto { id: 7, ..volume(3, Vec::new()) }],
+            storylineChapters: Vec::new(),
+        });
+
+        let config = test_config(json!({}));
+        let context = resolve_with(&api, &config).await.unwrap();
+        assert_eq!(context.series_name, "Series");
+        assert_eq!(context.chapter_title.as_deref(), Some("The Storm"));
+        assert_eq!(context.volume_number.as_deref(), Some("3"));
+        assert_eq!(context.author, None, "the path is only used when asked");
+        assert_eq!((context.page, context.pages), (5, 20));
+        assert_eq!(context.chapter_hours, Some(0.5));
+        assert_eq!(context.series_hours_left, None, "only fetched for series_remaining");
+        assert_eq!(context.kind, ReadingKind::Manga);
+        assert!(context.series_cover_url.unwrap().contains("seriesId=1"));
+        assert_eq!(context.chapter_cover_url, None);
+    }
+
+    #[tokio::test]
+    async fn resolve_takes_the_kind_from_the_library_type() {
+        for (library_type, kind) in [
+            (LibraryType::Comic, ReadingKind::Comic),
+            (LibraryType::LightNovel, ReadingKind::Book),
+            (LibraryType::Image, ReadingKind::Manga),
+        ] {
+            let mut api = fake();
+            api.chapters.insert(10, ChapterDto { chapterNumber: "-100000".to_string(), ..the_storm() });
+            api.library_types.insert(1, library_type);
+
+            let context = resolve_with(&api, &test_config(json!({}))).await.unwrap();
+            assert_eq!(context.kind, kind, "{:?}", library_type);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_falls_back_to_book_info_and_the_history_name() {
+        let mut api = FakeKavita::default();
+        api.book_info.insert(10, BookInfoDto {
+            volumeId: 0,
+            seriesName: "Series".to_string(),
+            chapterNumber: "-100000".to_string(),
+            pages: 300,
+            chapterTitle: Some("Prologue".to_string()),
+        });
+
+        let config = test_config(json!({}));
+        let context = resolve_with(&api, &config).await.unwrap();
+        assert_eq!(context.series_name, "History name");
+        assert_eq!(context.kind, ReadingKind::Book, "a -100000 chapter outside any volume");
+        assert_eq!(context.volume_number, None);
+        assert_eq!(context.pages, 300);
+        assert_eq!(context.author, None);
+    }
+
+    #[tokio::test]
+    async fn resolve_gives_up_without_any_chapter_lookup() {
+        let config = test_config(json!({}));
+        assert!(resolve_with(&fake(), &config).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn resolve_asks_for_the_time_left_in_the_series_when_the_timer_shows_it() {
+        let mut api = fake();
+        api.chapters.insert(10, the_storm());
+        api.time_left.insert(1, HourEstimateRangeDto { avgHours: 2.0 });
+
+        let config = test_config(json!({ "timestamp_mode": "series_remaining" }));
+        let context = resolve_with(&api, &config).await.unwrap();
+        assert_eq!(context.series_hours_left, Some(2.0));
+    }
+
+    #[tokio::test]
+    async fn resolve_finds_the_section_of_epubs_only() {
+        let cases = [
+            (MangaFormat::Epub, vec!["Part 2", "Chapter 7: The Storm"]),
+            (MangaFormat::Archive, vec![]),
+        ];
+        for (format, section) in cases {
+            let mut api = fake();
+            let info = ChapterInfoDto { seriesFormat: format, ..chapter_info() };
+            api.chapter_infos.insert(10, info);
+            api.book_chapters.insert(10, book());
+
+            let progress = ProgressDto { pageNum: 26, ..PROGRESS };
+            let (config, mut cache) = (test_config(json!({})), ResolveCache::default());
+            let context = resolve(&api, &config, &mut cache, &SpeedModel::default(), &progress, 1, "").await;
+            let context = context.unwrap();
+            assert_eq!(context.section, section, "{:?}", format);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_leaves_out_the_section_when_the_table_of_contents_fails_to_load() {
+        let mut api = fake();
+        api.chapter_infos.insert(10, ChapterInfoDto { seriesFormat: MangaFormat::Epub, ..chapter_info() });
+        api.errors.insert("book_chapters", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+
+        let context = resolve_with(&api, &test_config(json!({}))).await.unwrap();
+        assert!(context.section.is_empty());
+    }
+
+    #[tokio::test]
+    async fn resolve_caches_the_table_of_contents() {
+        let mut api = fake();
+        api.chapter_infos.insert(10, ChapterInfoDto { seriesFormat: MangaFormat::Epub, ..chapter_info() });
+        api.book_chapters.insert(10, book());
+        let config = test_config(json!({}));
+        let (mut cache, speed) = (ResolveCache::default(), SpeedModel::default());
+
+        let context = resolve(&api, &config, &mut cache, &speed, &PROGRESS, 1, "").await.unwrap();
+        assert_eq!(context.section, ["Part 1", "Chapter 1"]);
+
+        api.errors.insert("book_chapters", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        let progress = ProgressDto { pageNum: 12, ..PROGRESS };
+        let context = resolve(&api, &config, &mut cache, &speed, &progress, 1, "").await.unwrap();
+        assert_eq!(context.section, ["Part 1", "Chapter 2"]);
+    }
+
+    #[test]
+    fn toc_path_follows_nested_parts() {
+        assert_eq!(toc_path(&book(), 12), ["Part 1", "Chapter 2"]);
+        assert_eq!(toc_path(&book(), 26), ["Part 2", "Chapter 7: The Storm"]);
+        assert_eq!(toc_path(&book(), 40), ["Part 2", "Chapter 8"]);
+    }
+
+    #[test]
+    fn toc_path_stops_at_a_part_before_its_first_chapter() {
+        assert_eq!(toc_path(&book(), 21), ["Part 2"]);
+    }
+
+    #[test]
+    fn toc_path_is_empty_before_the_first_entry() {
+        assert!(toc_path(&book(), 0).is_empty());
+    }
+
+    #[test]
+    fn toc_path_does_not_depend_on_entry_order() {
+        let mut toc = book();
+        toc.reverse();
+        toc[0].children.reverse();
+        assert_eq!(toc_path(&toc, 26), ["Part 2", "Chapter 7: The Storm"]);
+    }
+
+    #[test]
+    fn toc_path_skips_untitled_entries() {
+        let toc = vec![entry("", 0, vec![entry("Prologue", 1, vec![])])];
+        assert_eq!(toc_path(&toc, 3), ["Prologue"]);
+    }
+
+    fn three_chapters() -> SeriesDetailDto {
+        SeriesDetailDto {
+            volumes: vec![volume(3, vec![chapter(9, 20.0), chapter(10, 21.0)])],
+            storylineChapters: vec![chapter(11, 22.0)],
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_places_the_chapter_in_the_series_when_a_template_asks() {
+        let mut api = fake();
+        api.chapter_infos.insert(10, chapter_info());
+        api.series_details.insert(1, three_chapters());
+
+        let context = resolve_with(&api, &test_config(json!({}))).await.unwrap();
+        assert_eq!(context.series_chapters, 0, "series detail is only fetched for its placeholders");
+
+        let config = test_config(json!({ "state_template": "{series_progress}" }));
+        let context = resolve_with(&api, &config).await.unwrap();
+        assert_eq!((context.series_chapter, context.series_chapters), (Some(2), 3));
+    }
+
+    #[tokio::test]
+    async fn resolve_keeps_the_series_detail_until_it_is_stale() {
+        let mut api = fake();
+        api.chapter_infos.insert(10, chapter_info());
+        api.series_details.insert(1, three_chapters());
+        let config = test_config(json!({ "state_template": "{series_progress}" }));
+        let (mut cache, speed) = (ResolveCache::default(), SpeedModel::default());
+        let resolve_chapters = async |api: &FakeKavita, cache: &mut ResolveCache| {
+            let context = resolve(api, &config, cache, &speed, &PROGRESS, 1, "").await.unwrap();
+            context.series_chapters
+        };
+        assert_eq!(resolve_chapters(&api, &mut cache).await, 3);
+
+        api.series_details.get_mut(&1).unwrap().storylineChapters.push(chapter(12, 23.0));
+        assert_eq!(resolve_chapters(&api, &mut cache).await, 3, "cached");
+
+        let (_, fetched_at) = cache.details.get_mut(&1).unwrap();
+        *fetched_at -= SERIES_REFRESH_INTERVAL;
+        assert_eq!(resolve_chapters(&api, &mut cache).await, 4, "refreshed");
+
+        let (_, fetched_at) = cache.details.get_mut(&1).unwrap();
+        *fetched_at -= SERIES_REFRESH_INTERVAL;
+        api.errors.insert("series_detail", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(resolve_chapters(&api, &mut cache).await, 4, "stale detail kept when the refresh fails");
+    }
+
+    #[test]
+    fn chapter_order_goes_through_volumes_then_loose_chapters() {
+        let detail = SeriesDetailDto {
+            volumes: vec![
+                volume(2, vec![chapter(4, 4.0), chapter(3, 3.0)]),
+                volume(1, vec![chapter(1, 1.0), chapter(2, 2.0)]),
+            ],
+            storylineChapters: vec![chapter(6, 6.0), chapter(5, 5.0)],
+        };
+        assert_eq!(chapter_order(&detail), [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn chapter_order_leaves_out_specials() {
+        let detail = SeriesDetailDto {
+            volumes: vec![volume(1, vec![chapter(1, 1.0), special(90)])],
+            storylineChapters: vec![special(91), chapter(2, 2.0)],
+        };
+        assert_eq!(chapter_order(&detail), [1, 2]);
+    }
+
+    #[test]
+    fn chapter_order_counts_loose_leaf_volumes_once() {
+        // A series of volume files: each volume holds one chapter numbered -100000.
+        let volume_file = |id, number| volume(number, vec![ChapterDto {
+            chapterNumber: "-100000".to_string(),
+            ..chapter(id, -100000.0)
+        }]);
+        let detail = SeriesDetailDto {
+            volumes: vec![volume_file(30, 3), volume_file(10, 1), volume_file(20, 2)],
+            storylineChapters: Vec::new(),
+        };
+        assert_eq!(chapter_order(&detail), [10, 20, 30]);
+    }
+
+    #[test]
+    fn chapter_order_skips_chapters_listed_twice() {
+        let detail = SeriesDetailDto {
+            volumes: vec![volume(1, vec![chapter(1, 1.0)])],
+            storylineChapters: vec![chapter(1, 1.0), chapter(2, 2.0)],
+        };
+        assert_eq!(chapter_order(&detail), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn resolve_looks_up_the_chapter_only_when_it_is_shown() {
+        let mut api = fake();
+        api.chapter_infos.insert(10, chapter_info());
+        api.chapters.insert(10, the_storm());
+
+        let context = resolve_with(&api, &test_config(json!({ "timestamp_mode": "none" }))).await.unwrap();
+        assert_eq!(context.chapter_hours, None, "the series has a cover and nothing counts down");
+
+        let context = resolve_with(&api, &test_config(json!({}))).await.unwrap();
+        assert_eq!(context.chapter_hours, Some(0.5), "no reading speed learned yet");
+    }
+
+    #[tokio::test]
+    async fn resolve_keeps_the_series_and_its_metadata_until_they_are_stale() {
+        let mut api = fake();
+        api.chapter_infos.insert(10, chapter_info());
+        let metadata = json!({ "genres": [{ "title": "Action" }] });
+        api.series_metadata.insert(1, serde_json::from_value(metadata).unwrap());
+        let config = test_config(json!({}));
+        let (mut cache, speed) = (ResolveCache::default(), SpeedModel::default());
+        let genres = |context: &ReadingContext| context.metadata.as_ref().map(|m| m.genres.len());
+
+        let context = resolve(&api, &config, &mut cache, &speed, &PROGRESS, 1, "").await.unwrap();
+        assert_eq!((context.series_name.as_str(), genres(&context)), ("Series", Some(1)));
+
+        api.series.get_mut(&1).unwrap().name = "Renamed".to_string();
+        api.series_metadata.get_mut(&1).unwrap().genres.clear();
+        let context = resolve(&api, &config, &mut cache, &speed, &PROGRESS, 1, "").await.unwrap();
+        assert_eq!((context.series_name.as_str(), genres(&context)), ("Series", Some(1)), "cached");
+
+        for (_, fetched_at) in cache.series.values_mut() {
+            *fetched_at -= SERIES_REFRESH_INTERVAL;
+        }
+        for (_, fetched_at) in cache.metadata.values_mut() {
+            *fetched_at -= SERIES_REFRESH_INTERVAL;
+        }
+        api.errors.insert("series_metadata", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        let context = resolve(&api, &config, &mut cache, &speed, &PROGRESS, 1, "").await.unwrap();
+        assert_eq!(context.series_name, "Renamed", "refreshed");
+        assert_eq!(genres(&context), Some(1), "stale metadata kept when the refresh fails");
+    }
+
+    #[tokio::test]
+    async fn resolve_caches_the_chapter_metadata() {
+        let mut api = fake();
+        api.chapter_infos.insert(10, chapter_info());
+        let chapter_metadata = json!({ "writers": [{ "name": "Guest Writer" }] });
+        api.chapter_metadata.insert(10, serde_json::from_value(chapter_metadata).unwrap());
+        let config = test_config(json!({}));
+        let (mut cache, speed) = (ResolveCache::default(), SpeedModel::default());
+
+        let context = resolve(&api, &config, &mut cache, &speed, &PROGRESS, 1, "").await.unwrap();
+        assert_eq!(context.author.as_deref(), Some("Guest Writer"));
+
+        api.errors.insert("chapter_metadata", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        let context = resolve(&api, &config, &mut cache, &speed, &PROGRESS, 1, "").await.unwrap();
+        assert_eq!(context.author.as_deref(), Some("Guest Writer"));
+
+        let next = ProgressDto { chapterId: 11, ..PROGRESS };
+        api.chapter_infos.insert(11, chapter_info());
+        let context = resolve(&api, &config, &mut cache, &speed, &next, 1, "").await.unwrap();
+        assert_eq!(context.author, None, "another chapter is looked up again");
+    }
+
+    #[tokio::test]
+    async fn resolve_caches_the_chapter() {
+        let mut api = fake();
+        api.chapter_infos.insert(10, chapter_info());
+        api.chapters.insert(10, the_storm());
+        let config = test_config(json!({}));
+        let (mut cache, speed) = (ResolveCache::default(), SpeedModel::default());
+
+        let context = resolve(&api, &config, &mut cache, &speed, &PROGRESS, 1, "").await.unwrap();
+        assert_eq!(context.chapter_hours, Some(0.5));
+
+        api.errors.insert("chapter", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        let context = resolve(&api, &config, &mut cache, &speed, &PROGRESS, 1, "").await.unwrap();
+        assert_eq!(context.chapter_hours, Some(0.5));
+    }
+}
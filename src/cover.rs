@@ -0,0 +1,114 @@
+//! Obscures covers of sensitive series before they are uploaded for Discord.
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat};
+use serde::Deserialize;
+use std::io::Cursor;
+
+/// Covers are shrunk to this width first; Discord shows them small anyway.
+const WORKING_WIDTH: u32 = 300;
+
+/// Width in blocks of a pixelated cover.
+const PIXELATE_BLOCKS: u32 = 12;
+
+/// How a privacy rule wants the cover shown.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CoverMode {
+    #[default]
+    Show,
+    Blur,
+    Pixelate,
+    /// Use `privacy.cover_placeholder` instead of the cover.
+    Placeholder,
+}
+
+/// Processing applied to the cover image itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverEffect {
+    Blur,
+    Pixelate,
+}
+
+impl CoverEffect {
+    pub fn name(self) -> &'static str {
+        match self {
+            CoverEffect::Blur => "blur",
+            CoverEffect::Pixelate => "pixelate",
+        }
+    }
+}
+
+/// Decodes a cover, applies `effect` and encodes it as PNG.
+pub fn apply(bytes: &[u8], effect: CoverEffect) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let image = image::load_from_memory(bytes)?;
+    let image = if image.width() > WORKING_WIDTH {
+        image.resize(WORKING_WIDTH, u32::MAX, FilterType::Triangle)
+    } else {
+        image
+    };
+
+    let processed = match effect {
+        CoverEffect::Blur => {
+            let sigma = image.width().max(image.height()) as f32 / 25.0;
+            DynamicImage::ImageRgba8(imageops::blur(&image.to_rgba8(), sigma))
+        },
+        CoverEffect::Pixelate => {
+            let (width, height) = (image.width(), image.height());
+            let blocks_high = (PIXELATE_BLOCKS * height / width.max(1)).max(1);
+            image
+                .resize_exact(PIXELATE_BLOCKS, blocks_high, FilterType::Triangle)
+                .resize_exact(width, height, FilterType::Nearest)
+        },
+    };
+
+    let mut png = Vec::new();
+    processed.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbaImage};
+
+    /// A striped PNG, `width` by `height`.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbaImage::from_fn(width, height, |x, _| image::Rgba([(x * 10) as u8, 0, 0, 255]));
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(image).write_to(&mut Cursor::new(&mut png), ImageFormat::Png).unwrap();
+        png
+    }
+
+    #[test]
+    fn apply_returns_a_png_the_size_of_the_cover() {
+        for effect in [CoverEffect::Blur, CoverEffect::Pixelate] {
+            let output = apply(&png(40, 60), effect).unwrap();
+            assert_eq!(image::guess_format(&output).unwrap(), ImageFormat::Png, "{}", effect.name());
+            let image = image::load_from_memory(&output).unwrap();
+            assert_eq!(image.dimensions(), (40, 60), "{}", effect.name());
+        }
+    }
+
+    #[test]
+    fn apply_changes_the_pixels() {
+        let input = png(40, 60);
+        for effect in [CoverEffect::Blur, CoverEffect::Pixelate] {
+            let before = image::load_from_memory(&input).unwrap().to_rgba8();
+            let after = image::load_from_memory(&apply(&input, effect).unwrap()).unwrap().to_rgba8();
+            assert_ne!(before, after, "{}", effect.name());
+        }
+    }
+
+    #[test]
+    fn apply_shrinks_large_covers() {
+        let output = apply(&png(600, 900), CoverEffect::Pixelate).unwrap();
+        let image = image::load_from_memory(&output).unwrap();
+        assert_eq!(image.dimensions(), (WORKING_WIDTH, 450));
+    }
+
+    #[test]
+    fn apply_rejects_data_that_is_not_an_image() {
+        assert!(apply(b"<html>Not found</html>", CoverEffect::Blur).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! Picks the author shown on Discord from the people Kavita credits.
+//!
+//! `author_roles` is tried in order; the first role with anyone credited wins,
+//! looking at the chapter's credits before the series'.
+
+use serde::Deserialize;
+
+use crate::kavita::{ChapterMetadataDto, CreditsDto, PersonDto, SeriesMetadataDto};
+use crate::Config;
+
+const DEFAULT_ROLES: [Role; 3] = [Role::Writer, Role::Artist, Role::Publisher];
+
+/// At most this many names are shown, so long credit lists fit Discord.
+const MAX_NAMES: usize = 2;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Writer,
+    /// Pencillers, inkers and cover artists.
+    Artist,
+    Penciller,
+    Inker,
+    CoverArtist,
+    Colorist,
+    Letterer,
+    Editor,
+    Translator,
+    Publisher,
+}
+
+impl Role {
+    fn people(self, credits: &CreditsDto) -> Vec<&PersonDto> {
+        let lists: &[&Vec<PersonDto>] = match self {
+            Role::Writer => &[&credits.writers],
+            Role::Artist => &[&credits.pencillers, &credits.inkers, &credits.coverArtists],
+            Role::Penciller => &[&credits.pencillers],
+            Role::Inker => &[&credits.inkers],
+            Role::CoverArtist => &[&credits.coverArtists],
+            Role::Colorist => &[&credits.colorists],
+            Role::Letterer => &[&credits.letterers],
+            Role::Editor => &[&credits.editors],
+            Role::Translator => &[&credits.translators],
+            Role::Publisher => &[&credits.publishers],
+        };
+        lists.iter().flat_map(|list| list.iter()).collect()
+    }
+}
+
+/// The author to show, or `None` when nobody in `author_roles` is credited and
+/// `author_from_path` is off.
+pub fn author(
+    config: &Config,
+    chapter: Option<&ChapterMetadataDto>,
+    series: Option<&SeriesMetadataDto>,
+    file_path: Option<&str>,
+) -> Option<String> {
+    let roles = config.author_roles.as_deref().unwrap_or(&DEFAULT_ROLES);
+    let credits = [chapter.map(|c| &c.credits), series.map(|s| &s.credits)];
+
+    for role in roles {
+        for credits in credits.iter().flatten() {
+            let names = names(role.people(credits));
+            if !names.is_empty() {
+                return Some(names.join(", "));
+            }
+        }
+    }
+
+    if config.author_from_path.unwrap_or(false) {
+        return file_path
+            .and_then(|path| path.split('/').nth(2))
+            .filter(|folder| !folder.is_empty())
+            .map(str::to_string);
+    }
+    None
+}
+
+/// The first few distinct, non-empty names.
+fn names(people: Vec<&PersonDto>) -> Vec<&str> {
+    let mut names: Vec<&str> = Vec::new();
+    for person in people {
+        let name = person.name.trim();
+        if !name.is_empty() && !names.contains(&name) && names.len() < MAX_NAMES {
+            names.push(name);
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_config;
+    use serde_json::json;
+
+    fn series(credits: serde_json::Value) -> SeriesMetadataDto {
+        serde_json::from_value(credits).unwrap()
+    }
+
+    fn chapter(credits: serde_json::Value) -> ChapterMetadataDto {
+        serde_json::from_value(credits).unwrap()
+    }
+
+    fn people(names: &[&str]) -> serde_json::Value {
+        names.iter().map(|name| json!({ "name": name })).collect()
+    }
+
+    #[test]
+    fn author_tries_the_roles_in_order() {
+        let credits = series(json!({ "inkers": people(&["Inker"]), "publishers": people(&["Press"]) }));
+        assert_eq!(author(&test_config(json!({})), None, Some(&credits), None).as_deref(), Some("Inker"));
+
+        let config = test_config(json!({ "author_roles": ["publisher", "artist"] }));
+        assert_eq!(author(&config, None, Some(&credits), None).as_deref(), Some("Press"));
+
+        let config = test_config(json!({ "author_roles": ["letterer"] }));
+        assert_eq!(author(&config, None, Some(&credits), None), None);
+    }
+
+    #[test]
+    fn author_prefers_the_chapter_credits_for_the_same_role() {
+        let config = test_config(json!({}));
+        let series = series(json!({ "writers": people(&["Series Writer"]) }));
+
+        let guest = chapter(json!({ "writers": people(&["Guest Writer"]) }));
+        assert_eq!(author(&config, Some(&guest), Some(&series), None).as_deref(), Some("Guest Writer"));
+
+        // An earlier role on the series wins over a later one on the chapter.
+        let drawn = chapter(json!({ "pencillers": people(&["Penciller"]) }));
+        assert_eq!(author(&config, Some(&drawn), Some(&series), None).as_deref(), Some("Series Writer"));
+    }
+
+    #[test]
+    fn author_names_at_most_two_distinct_people() {
+        let credits = series(json!({ "writers": people(&["A", " ", "B", "A", "C"]) }));
+        assert_eq!(author(&test_config(json!({})), None, Some(&credits), None).as_deref(), Some("A, B"));
+    }
+
+    #[test]
+    fn author_from_path_is_opt_in() {
+        let path = Some("/manga/Folder Author/Series/1.cbz");
+        assert_eq!(author(&test_config(json!({})), None, None, path), None);
+
+        let config = test_config(json!({ "author_from_path": true }));
+        assert_eq!(author(&config, None, None, path).as_deref(), Some("Folder Author"));
+        assert_eq!(author(&config, None, None, Some("1.cbz")), None);
+
+        let credits = series(json!({ "writers": people(&["Writer"]) }));
+        let credited = author(&config, None, Some(&credits), path);
+        assert_eq!(credited.as_deref(), Some("Writer"), "credits come first");
+    }
+}
@@ -0,0 +1,510 @@
+//! Works out which chapter the user is reading right now.
+//!
+//! Kavita's own readers leave fresh rows in the reading history, which is the
+//! cheapest signal. Third-party clients (Tachiyomi/Mihon, OPDS readers) save
+//! progress without doing so, so the other detectors compare snapshots of
+//! progress between polls and treat a change as reading activity. What they
+//! watch comes from the reading history and from the series on deck, since
+//! those clients may never add to the history at all.
+
+use chrono::NaiveDateTime;
+use log::{error, info};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::kavita::{self, KavitaApi, ProgressDto, ReadHistoryEvent};
+use crate::Config;
+
+/// Series whose progress is watched for changes.
+const MAX_WATCHED_SERIES: usize = 10;
+
+/// Chapters whose progress is watched for changes.
+const MAX_WATCHED_CHAPTERS: usize = 20;
+
+/// How long the series on deck and where to continue them are reused for.
+const ON_DECK_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// A way of noticing reading activity, enabled through `detectors`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Detector {
+    /// Recent entries in `/api/Stats/user/reading-history`.
+    ReadingHistory,
+    /// Pages read changing in `/api/Reader/all-chapter-progress` for recently read chapters.
+    ChapterProgress,
+    /// The last fully read chapter changing in `/api/Tachiyomi/latest-chapter`.
+    TachiyomiLatest,
+}
+
+const DEFAULT_DETECTORS: &[Detector] = &[Detector::ReadingHistory];
+
+/// The progress being read, with the series id and name it belongs to.
+pub type Activity = (ProgressDto, i32, String);
+
+#[derive(Debug, Clone)]
+struct Watched {
+    chapter_id: i32,
+    series_id: i32,
+    series_name: String,
+}
+
+#[derive(Debug, Clone)]
+struct Change {
+    watched: Watched,
+    at: NaiveDateTime,
+}
+
+/// The last fully read chapter of a series, and the chapter after it.
+#[derive(Debug, Clone, Copy)]
+struct Latest {
+    chapter_id: i32,
+    next_id: Option<i32>,
+}
+
+/// Remembers the progress snapshots from the previous poll.
+#[derive(Debug, Default)]
+pub struct ActivityDetector {
+    /// Pages read per chapter.
+    pages_read: HashMap<i32, i32>,
+    /// Last fully read chapter per series.
+    latest_chapters: HashMap<i32, Latest>,
+    /// Where to continue each series on deck, and when that was looked up.
+    on_deck: Option<(Vec<Watched>, Instant)>,
+    last_change: Option<Change>,
+}
+
+impl ActivityDetector {
+    /// Runs the configured detectors and returns activity from within the
+    /// inactivity timeout. The reading history wins when it has any, since it
+    /// also covers chapters no snapshot is watching.
+    pub async fn detect(
+        &mut self,
+        api: &impl KavitaApi,
+        config: &Config,
+    ) -> Result<Option<Activity>, Box<dyn std::error::Error>> {
+        let detectors = config.detectors.as_deref().unwrap_or(DEFAULT_DETECTORS);
+        let watches_snapshots = detectors.iter().any(|detector| *detector != Detector::ReadingHistory);
+
+        let user_id = match api.current_user_id().await {
+            Ok(id) => id,
+            Err(e) if kavita::is_unauthorized(e.as_ref()) => return Err(e),
+            Err(e) => {
+                error!("Failed to get account info: {}", e);
+                1
+            }
+        };
+
+        // The snapshot detectors can still work without the history.
+        let mut events = match api.reading_history(user_id).await {
+            Ok(events) => events,
+            Err(e) if kavita::is_unauthorized(e.as_ref()) || !watches_snapshots => return Err(e),
+            Err(e) => {
+                error!("Failed to get reading history: {}", e);
+                Vec::new()
+            }
+        };
+        events.sort_by(|a, b| b.readDate.cmp(&a.readDate));
+
+        let recent_threshold = (config.inactivity_timeout_minutes
+            .unwrap_or(15) * 60) as i64;
+        let now = chrono::Utc::now().naive_utc();
+
+        if watches_snapshots {
+            let on_deck = self.on_deck(api).await;
+            let mut watched = watched_chapters(&events, &on_deck);
+
+            if detectors.contains(&Detector::TachiyomiLatest) {
+                let series = watched_series(&events, &on_deck);
+                for latest in self.diff_latest_chapters(api, &series, now).await {
+                    if !watched.iter().any(|w| w.chapter_id == latest.chapter_id) {
+                        watched.push(latest);
+                    }
+                }
+            }
+
+            if detectors.contains(&Detector::ChapterProgress) {
+                if let Some(change) = &self.last_change {
+                    if !watched.iter().any(|w| w.chapter_id == change.watched.chapter_id) {
+                        watched.push(change.watched.clone());
+                    }
+                }
+                self.diff_chapter_progress(api, user_id, &watched, now).await;
+            }
+        }
+
+        if detectors.contains(&Detector::ReadingHistory) {
+            if let Some(activity) = from_history(api, &events, now, recent_threshold).await {
+                return Ok(Some(activity));
+            }
+        }
+
+        let Some(change) = &self.last_change else {
+            return Ok(None);
+        };
+
+        let seconds_ago = (now - change.at).num_seconds();
+        if seconds_ago >= recent_threshold {
+            return Ok(None);
+        }
+
+        info!("Last progress change: chapter {} of series {}, {} seconds ago",
+              change.watched.chapter_id, change.watched.series_id, seconds_ago);
+
+        match api.progress(change.watched.chapter_id).await {
+            Ok(progress) => Ok(Some((progress, change.watched.series_id, change.watched.series_name.clone()))),
+            Err(e) => {
+                error!("Failed to get progress: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// The chapter to continue each series on deck from, refreshed every few minutes.
+    async fn on_deck(&mut self, api: &impl KavitaApi) -> Vec<Watched> {
+        if let Some((watched, fetched_at)) = &self.on_deck {
+            if fetched_at.elapsed() < ON_DECK_REFRESH_INTERVAL {
+                return watched.clone();
+            }
+        }
+
+        let series = match api.on_deck(MAX_WATCHED_SERIES).await {
+            Ok(series) => series,
+            Err(e) => {
+                error!("Failed to get the series on deck: {}", e);
+                return self.on_deck.as_ref().map(|(watched, _)| watched.clone()).unwrap_or_default();
+            }
+        };
+
+        let mut watched = Vec::new();
+        for series in series.into_iter().filter(|series| series.id > 0) {
+            match api.continue_point(series.id).await {
+                Ok(chapter) if chapter.id > 0 => watched.push(Watched {
+                    chapter_id: chapter.id,
+                    series_id: series.id,
+                    series_name: series.name,
+                }),
+                Ok(_) => {},
+                Err(e) => error!("Failed to get the continue point of series {}: {}", series.id, e),
+            }
+        }
+
+        self.on_deck = Some((watched.clone(), Instant::now()));
+        watched
+    }
+
+    /// Compares the last fully read chapter of each watched series with the
+    /// previous poll. A newly finished chapter means the reader has moved on
+    /// to the next one, so that is what changed. Returns the chapters after
+    /// the latest ones so their page progress can be watched too.
+    async fn diff_latest_chapters(
+        &mut self,
+        api: &impl KavitaApi,
+        series: &[Watched],
+        now: NaiveDateTime,
+    ) -> Vec<Watched> {
+        let mut reading = Vec::new();
+
+        for series in series {
+            let chapter = match api.latest_chapter(series.series_id).await {
+                Ok(Some(chapter)) if chapter.id > 0 => chapter,
+                Ok(_) => continue,
+                Err(e) => {
+                    error!("Failed to get latest chapter for series {}: {}", series.series_id, e);
+                    continue;
+                }
+            };
+
+            let previous = self.latest_chapters.get(&series.series_id).copied();
+            let latest = match previous {
+                Some(previous) if previous.chapter_id == chapter.id => previous,
+                _ => {
+                    let next = api.next_chapter(series.series_id, chapter.volumeId, chapter.id).await;
+                    let next_id = match next {
+                        Ok(next_id) => next_id,
+                        Err(e) => {
+                            error!("Failed to get the chapter after {}: {}", chapter.id, e);
+                            None
+                        }
+                    };
+                    let latest = Latest { chapter_id: chapter.id, next_id };
+                    self.latest_chapters.insert(series.series_id, latest);
+                    latest
+                }
+            };
+
+            let watched = Watched {
+                // After the last chapter there is nothing further to be reading.
+                chapter_id: latest.next_id.unwrap_or(latest.chapter_id),
+                ..series.clone()
+            };
+
+            if previous.is_some_and(|previous| previous.chapter_id != latest.chapter_id) {
+                info!("Latest read chapter of series {} changed to {}, now reading {}",
+                      series.series_id, latest.chapter_id, watched.chapter_id);
+                self.last_change = Some(Change { watched: watched.clone(), at: now });
+            }
+
+            reading.push(watched);
+        }
+
+        reading
+    }
+
+    /// Compares the pages read in each watched chapter with the previous poll.
+    async fn diff_chapter_progress(
+        &mut self,
+        api: &impl KavitaApi,
+        user_id: i32,
+        watched: &[Watched],
+        now: NaiveDateTime,
+    ) {
+        for chapter in watched {
+            let pages_read = match api.chapter_progress(chapter.chapter_id).await {
+                Ok(entries) => entries.iter()
+                    .find(|entry| entry.appUserId == user_id)
+                    .map_or(0, |entry| entry.pagesRead),
+                Err(e) => {
+                    error!("Failed to get progress for chapter {}: {}", chapter.chapter_id, e);
+                    continue;
+                }
+            };
+
+            let previous = self.pages_read.insert(chapter.chapter_id, pages_read);
+            if previous.is_some_and(|previous| previous != pages_read) {
+                info!("Pages read in chapter {} changed to {}", chapter.chapter_id, pages_read);
+                self.last_change = Some(Change { watched: chapter.clone(), at: now });
+            }
+        }
+    }
+}
+
+/// The most recent reading history entry, if it is within the threshold.
+async fn from_history(
+    api: &impl KavitaApi,
+    events: &[ReadHistoryEvent],
+    now: NaiveDateTime,
+    recent_threshold: i64,
+) -> Option<Activity> {
+    let most_recent = events.first()?;
+
+    let read_date = most_recent.readDate.clone();
+    info!("Last reading timestamp: {}", read_date);
+
+    let read_date_utc = most_recent.readDateUtc.clone();
+    info!("Last reading timestamp (UTC): {}", read_date_utc);
+
+    let event_time = match chrono::DateTime::parse_from_rfc3339(&read_date_utc) {
+        Ok(dt) => dt.naive_utc(),
+        Err(e) => {
+            match chrono::NaiveDateTime::parse_from_str(
+                read_date_utc.split('.').next().unwrap_or(&read_date_utc),
+                "%Y-%m-%dT%H:%M:%S"
+            ) {
+                Ok(dt) => dt,
+                Err(e2) => {
+                    error!("Error parsing UTC date '{}': {} (second attempt: {}). Using current time.",
+                           read_date_utc, e, e2);
+                    chrono::Utc::now().naive_utc()
+                }
+            }
+        }
+    };
+
+    let seconds_ago = (now - event_time).num_seconds();
+    info!("Last activity: {} seconds ago (UTC comparison)", seconds_ago);
+
+    if seconds_ago < recent_threshold {
+        match api.progress(most_recent.chapterId).await {
+            Ok(progress) => {
+                return Some((progress, most_recent.seriesId, most_recent.seriesName.clone()));
+            },
+            Err(e) => error!("Failed to get progress: {}", e)
+        }
+    }
+
+    None
+}
+
+/// The newest history entry of each series, most recent first, then the
+/// series on deck.
+fn watched_series(events: &[ReadHistoryEvent], on_deck: &[Watched]) -> Vec<Watched> {
+    let mut series: Vec<Watched> = Vec::new();
+    for watched in from_events(events).chain(on_deck.iter().cloned()) {
+        if series.len() >= MAX_WATCHED_SERIES {
+            break;
+        }
+        if !series.iter().any(|s| s.series_id == watched.series_id) {
+            series.push(watched);
+        }
+    }
+    series
+}
+
+/// Chapters from the reading history, most recent first, then where to
+/// continue the series on deck.
+fn watched_chapters(events: &[ReadHistoryEvent], on_deck: &[Watched]) -> Vec<Watched> {
+    let mut watched: Vec<Watched> = Vec::new();
+    for chapter in from_events(events).chain(on_deck.iter().cloned()) {
+        if watched.len() >= MAX_WATCHED_CHAPTERS {
+            break;
+        }
+        if !watched.iter().any(|w| w.chapter_id == chapter.chapter_id) {
+            watched.push(chapter);
+        }
+    }
+    watched
+}
+
+fn from_events(events: &[ReadHistoryEvent]) -> impl Iterator<Item = Watched> + '_ {
+    events.iter().map(|event| Watched {
+        chapter_id: event.chapterId,
+        series_id: event.seriesId,
+        series_name: event.seriesName.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kavita::fake::FakeKavita;
+    use crate::kavita::{ChapterDto, FullProgressDto};
+    use crate::test_config;
+    use serde_json::json;
+
+    fn event(series_id: i32, chapter_id: i32, minutes_ago: i64) -> ReadHistoryEvent {
+        let read = chrono::Utc::now() - chrono::Duration::minutes(minutes_ago);
+        ReadHistoryEvent {
+            seriesId: series_id,
+            seriesName: format!("Series {}", series_id),
+            readDate: read.naive_local().format("%Y-%m-%dT%H:%M:%S").to_string(),
+            readDateUtc: read.to_rfc3339(),
+            chapterId: chapter_id,
+        }
+    }
+
+    fn progress(chapter_id: i32, page: i32) -> ProgressDto {
+        ProgressDto { chapterId: chapter_id, pageNum: page, libraryId: 1 }
+    }
+
+    fn chapter(id: i32) -> ChapterDto {
+        let chapter = json!({ "id": id, "range": id.to_string(), "pages": 20, "volumeId": 1 });
+        serde_json::from_value(chapter).unwrap()
+    }
+
+    fn pages_read(pages: i32) -> Vec<FullProgressDto> {
+        vec![FullProgressDto { pagesRead: pages, appUserId: 1 }]
+    }
+
+    /// Series 1 on deck, continued from chapter 10, with nothing in the reading history.
+    fn on_deck() -> FakeKavita {
+        let mut api = FakeKavita {
+            user_id: 1,
+            on_deck: vec![serde_json::from_value(json!({ "id": 1, "name": "Series 1" })).unwrap()],
+            ..Default::default()
+        };
+        api.continue_points.insert(1, chapter(10));
+        api
+    }
+
+    #[tokio::test]
+    async fn detect_reports_recent_history() {
+        let mut api = FakeKavita { history: vec![event(2, 20, 30), event(1, 10, 1)], ..Default::default() };
+        api.progress.insert(10, progress(10, 4));
+
+        let activity = ActivityDetector::default().detect(&api, &test_config(json!({}))).await.unwrap();
+        let (progress, series_id, series_name) = activity.unwrap();
+        assert_eq!((progress.chapterId, progress.pageNum), (10, 4));
+        assert_eq!((series_id, series_name.as_str()), (1, "Series 1"));
+    }
+
+    #[tokio::test]
+    async fn detect_ignores_history_past_the_inactivity_timeout() {
+        let mut api = FakeKavita { history: vec![event(1, 10, 60)], ..Default::default() };
+        api.progress.insert(10, progress(10, 4));
+
+        let activity = ActivityDetector::default().detect(&api, &test_config(json!({}))).await.unwrap();
+        assert!(activity.is_none());
+    }
+
+    #[tokio::test]
+    async fn detect_passes_on_a_rejected_token() {
+        let mut api = FakeKavita::default();
+        api.errors.insert("current_user_id", reqwest::StatusCode::UNAUTHORIZED);
+
+        let error = ActivityDetector::default().detect(&api, &test_config(json!({}))).await.unwrap_err();
+        assert!(kavita::is_unauthorized(error.as_ref()));
+    }
+
+    #[tokio::test]
+    async fn chapter_progress_notices_pages_read_changing_between_polls() {
+        let config = test_config(json!({ "detectors": ["chapter_progress"] }));
+        let mut detector = ActivityDetector::default();
+        let mut api = on_deck();
+        api.chapter_progress.insert(10, pages_read(3));
+        api.progress.insert(10, progress(10, 5));
+
+        assert!(detector.detect(&api, &config).await.unwrap().is_none(), "the first poll is only a snapshot");
+        assert!(detector.detect(&api, &config).await.unwrap().is_none(), "nothing changed");
+
+        api.chapter_progress.insert(10, pages_read(5));
+        let (progress, series_id, series_name) = detector.detect(&api, &config).await.unwrap().unwrap();
+        assert_eq!(progress.chapterId, 10);
+        assert_eq!((series_id, series_name.as_str()), (1, "Series 1"));
+    }
+
+    #[tokio::test]
+    async fn tachiyomi_latest_moves_on_to_the_next_chapter() {
+        let config = test_config(json!({ "detectors": ["tachiyomi_latest"] }));
+        let mut detector = ActivityDetector::default();
+        let mut api = on_deck();
+        api.latest_chapters.insert(1, chapter(10));
+        api.next_chapters.insert(10, 11);
+        api.next_chapters.insert(11, 12);
+        api.progress.insert(12, progress(12, 0));
+
+        assert!(detector.detect(&api, &config).await.unwrap().is_none());
+
+        api.latest_chapters.insert(1, chapter(11));
+        let (progress, series_id, _) = detector.detect(&api, &config).await.unwrap().unwrap();
+        assert_eq!((progress.chapterId, series_id), (12, 1));
+    }
+
+    #[tokio::test]
+    async fn tachiyomi_latest_stays_on_the_last_chapter_of_a_series() {
+        let config = test_config(json!({ "detectors": ["tachiyomi_latest"] }));
+        let mut detector = ActivityDetector::default();
+        let mut api = on_deck();
+        api.latest_chapters.insert(1, chapter(10));
+        api.next_chapters.insert(10, 11);
+        api.progress.insert(11, progress(11, 20));
+
+        assert!(detector.detect(&api, &config).await.unwrap().is_none());
+
+        api.latest_chapters.insert(1, chapter(11));
+        let (progress, _, _) = detector.detect(&api, &config).await.unwrap().unwrap();
+        assert_eq!(progress.chapterId, 11);
+    }
+
+    #[tokio::test]
+    async fn detect_carries_on_without_the_history_when_snapshots_are_watched() {
+        let config = test_config(json!({ "detectors": ["reading_history", "chapter_progress"] }));
+        let mut detector = ActivityDetector::default();
+        let mut api = on_deck();
+        api.errors.insert("reading_history", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        api.chapter_progress.insert(10, pages_read(3));
+        api.progress.insert(10, progress(10, 5));
+
+        assert!(detector.detect(&api, &config).await.unwrap().is_none());
+        api.chapter_progress.insert(10, pages_read(4));
+        assert!(detector.detect(&api, &config).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn detect_fails_without_the_history_when_it_is_the_only_detector() {
+        let mut api = on_deck();
+        api.errors.insert("reading_history", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+
+        assert!(ActivityDetector::default().detect(&api, &test_config(json!({}))).await.is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use log::{error, info};
+use reqwest::{Client, Method};
+use serde::de::DeserializeOwned;
+
+use super::{
+    ApiResult, BookChapterItem, BookInfoDto, ChapterDto, ChapterInfoDto, ChapterMetadataDto,
+    CollectionDto, ExternalSeriesDto, FullProgressDto, HourEstimateRangeDto, KavitaApi, LibraryType,
+    ProgressDto, ReadHistoryEvent, ReadingListDto, ReadingListItemDto, SeriesDetailDto, SeriesDto,
+    SeriesMetadataDto, StatusError,
+};
+
+/// [`KavitaApi`] over HTTP, authenticated with a session JWT.
+pub struct KavitaClient<'a> {
+    client: &'a Client,
+    base_url: &'a str,
+    token: &'a str,
+}
+
+impl<'a> KavitaClient<'a> {
+    pub fn new(client: &'a Client, base_url: &'a str, token: &'a str) -> Self {
+        KavitaClient { client, base_url, token }
+    }
+
+    async fn get_text(&self, endpoint: &str, query: &[(&str, String)]) -> ApiResult<String> {
+        self.send_text(Method::GET, endpoint, query).await
+    }
+
+    async fn send_text(&self, method: Method, endpoint: &str, query: &[(&str, String)]) -> ApiResult<String> {
+        let url = format!("{}{}", self.base_url, endpoint);
+        info!("Requesting {} {} {:?}", method, url, query);
+
+        let response = self.client
+            .request(method, &url)
+            .query(query)
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(Box::new(StatusError {
+                endpoint: endpoint.to_string(),
+                status: response.status(),
+            }));
+        }
+
+        Ok(response.text().await?)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, endpoint: &str, query: &[(&str, String)]) -> ApiResult<T> {
+        self.send(Method::GET, endpoint, query).await
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        query: &[(&str, String)],
+    ) -> ApiResult<T> {
+        let text = self.send_text(method, endpoint, query).await?;
+        serde_json::from_str::<T>(&text).map_err(|e| {
+            error!("Failed to parse response from {}: {}", endpoint, e);
+            error!("Raw response: {}", text);
+            e.into()
+        })
+    }
+}
+
+impl KavitaApi for KavitaClient<'_> {
+    async fn current_user_id(&self) -> ApiResult<i32> {
+        // Some server versions return the member as an object, others as a one-element array.
+        let account: serde_json::Value = self.get("/api/Users/myself", &[]).await?;
+        let member = match &account {
+            serde_json::Value::Array(accounts) => accounts.first(),
+            other => Some(other),
+        };
+
+        member
+            .and_then(|m| m.get("id"))
+            .and_then(|v| v.as_i64())
+            .map(|id| id as i32)
+            .ok_or_else(|| "Account response did not contain a user id".into())
+    }
+
+    async fn reading_history(&self, user_id: i32) -> ApiResult<Vec<ReadHistoryEvent>> {
+        let text = self
+            .get_text("/api/Stats/user/reading-history", &[("userId", user_id.to_string())])
+            .await?;
+
+        if text.contains("<!doctype html>") || text.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    async fn progress(&self, chapter_id: i32) -> ApiResult<ProgressDto> {
+        self.get("/api/Reader/get-progress", &[("chapterId", chapter_id.to_string())]).await
+    }
+
+    async fn chapter_progress(&self, chapter_id: i32) -> ApiResult<Vec<FullProgressDto>> {
+        self.get("/api/Reader/all-chapter-progress", &[("chapterId", chapter_id.to_string())]).await
+    }
+
+    async fn chapter_info(&self, chapter_id: i32) -> ApiResult<ChapterInfoDto> {
+        self.get("/api/Reader/chapter-info", &[("chapterId", chapter_id.to_string())]).await
+    }
+
+    async fn chapter(&self, chapter_id: i32) -> ApiResult<ChapterDto> {
+        self.get("/api/Chapter", &[("chapterId", chapter_id.to_string())]).await
+    }
+
+    async fn series(&self, series_id: i32) -> ApiResult<SeriesDto> {
+        self.get(&format!("/api/Series/{}", series_id), &[]).await
+    }
+
+    async fn series_detail(&self, series_id: i32) -> ApiResult<SeriesDetailDto> {
+        self.get("/api/Series/series-detail", &[("seriesId", series_id.to_string())]).await
+    }
+
+    async fn series_metadata(&self, series_id: i32) -> ApiResult<SeriesMetadataDto> {
+        self.get("/api/Series/metadata", &[("seriesId", series_id.to_string())]).await
+    }
+
+    async fn chapter_metadata(&self, chapter_id: i32) -> ApiResult<ChapterMetadataDto> {
+        self.get("/api/Series/chapter-metadata", &[("chapterId", chapter_id.to_string())]).await
+    }
+
+    async fn external_series(&self, series_id: i32) -> ApiResult<ExternalSeriesDto> {
+        self.get("/api/Series/external-series-detail", &[("seriesId", series_id.to_string())]).await
+    }
+
+    async fn book_info(&self, chapter_id: i32) -> ApiResult<BookInfoDto> {
+        self.get(&format!("/api/Book/{}/book-info", chapter_id), &[]).await
+    }
+
+    async fn book_chapters(&self, chapter_id: i32) -> ApiResult<Vec<BookChapterItem>> {
+        self.get(&format!("/api/Book/{}/chapters", chapter_id), &[]).await
+    }
+
+    async fn time_left(&self, series_id: i32) -> ApiResult<HourEstimateRangeDto> {
+        self.get("/api/Reader/time-left", &[("seriesId", series_id.to_string())]).await
+    }
+
+    async fn latest_chapter(&self, series_id: i32) -> ApiResult<Option<ChapterDto>> {
+        let text = self
+            .get_text("/api/Tachiyomi/latest-chapter", &[("seriesId", series_id.to_string())])
+            .await?;
+
+        if text.trim().is_empty() {
+            return Ok(None);
+        }
+
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    async fn on_deck(&self, count: usize) -> ApiResult<Vec<SeriesDto>> {
+        self.send(
+            Method::POST,
+            "/api/Series/on-deck",
+            &[("PageNumber", "1".to_string()), ("PageSize", count.to_string())],
+        ).await
+    }
+
+    async fn continue_point(&self, series_id: i32) -> ApiResult<ChapterDto> {
+        self.get("/api/Reader/continue-point", &[("seriesId", series_id.to_string())]).await
+    }
+
+    async fn next_chapter(&self, series_id: i32, volume_id: i32, chapter_id: i32) -> ApiResult<Option<i32>> {
+        let next: i32 = self.get(
+            "/api/Reader/next-chapter",
+            &[
+                ("seriesId", series_id.to_string()),
+                ("volumeId", volume_id.to_string()),
+                ("currentChapterId", chapter_id.to_string()),
+            ],
+        ).await?;
+        Ok(Some(next).filter(|id| *id > 0))
+    }
+
+    async fn collections(&self) -> ApiResult<Vec<CollectionDto>> {
+        self.get("/api/Collection", &[("ownedOnly", "false".to_string())]).await
+    }
+
+    async fn series_collections(&self, series_id: i32) -> ApiResult<Vec<CollectionDto>> {
+        self.get(
+            "/api/Collection/all-series",
+            &[("seriesId", series_id.to_string()), ("ownedOnly", "false".to_string())],
+        ).await
+    }
+
+    async fn series_reading_lists(&self, series_id: i32) -> ApiResult<Vec<ReadingListDto>> {
+        self.get("/api/ReadingList/lists-for-series", &[("seriesId", series_id.to_string())]).await
+    }
+
+    async fn chapter_reading_lists(&self, chapter_id: i32) -> ApiResult<Vec<ReadingListDto>> {
+        self.get("/api/ReadingList/lists-for-chapter", &[("chapterId", chapter_id.to_string())]).await
+    }
+
+    async fn reading_list_items(&self, reading_list_id: i32) -> ApiResult<Vec<ReadingListItemDto>> {
+        self.get("/api/ReadingList/items", &[("readingListId", reading_list_id.to_string())]).await
+    }
+
+    async fn library_allows_scrobbling(&self, series_id: i32) -> ApiResult<bool> {
+        self.get("/api/Scrobbling/library-allows-scrobbling", &[("seriesId", series_id.to_string())]).await
+    }
+
+    async fn has_scrobble_hold(&self, series_id: i32) -> ApiResult<bool> {
+        self.get("/api/Scrobbling/has-hold", &[("seriesId", series_id.to_string())]).await
+    }
+
+    async fn server_base_url(&self) -> ApiResult<String> {
+        // Sent as plain text or as a JSON string depending on the Accept header.
+        let text = self.get_text("/api/Settings/base-url", &[]).await?;
+        Ok(text.trim().trim_matches('"').to_string())
+    }
+
+    async fn library_type(&self, library_id: i32) -> ApiResult<LibraryType> {
+        self.get("/api/Library/type", &[("libraryId", library_id.to_string())]).await
+    }
+}
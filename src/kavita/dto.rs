@@ -0,0 +1,487 @@
+use serde::{Deserialize, Deserializer};
+
+/// For fields Kavita may send as `null`: `#[serde(default)]` only covers a
+/// missing field.
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReadHistoryEvent {
+    pub seriesId: i32,
+    pub seriesName: String,
+    pub readDate: String,
+    pub readDateUtc: String,
+    pub chapterId: i32,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProgressDto {
+    pub chapterId: i32,
+    pub pageNum: i32,
+    pub libraryId: i32,
+}
+
+/// `/api/Reader/chapter-info`: the chapter, its volume, series and library in one response.
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct ChapterInfoDto {
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub chapterNumber: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub volumeNumber: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub seriesName: String,
+    #[serde(default)]
+    pub seriesFormat: MangaFormat,
+    pub libraryType: LibraryType,
+    pub chapterTitle: Option<String>,
+    pub pages: i32,
+    #[serde(default)]
+    pub isSpecial: bool,
+    #[serde(default)]
+    pub seriesTotalPages: i32,
+    #[serde(default)]
+    pub seriesTotalPagesRead: i32,
+}
+
+/// One user's progress in a chapter, from `/api/Reader/all-chapter-progress`.
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct FullProgressDto {
+    pub pagesRead: i32,
+    pub appUserId: i32,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct ChapterDto {
+    pub id: i32,
+    pub range: String,
+    pub title: Option<String>,
+    pub pages: i32,
+    pub coverImage: Option<String>,
+    pub volumeId: i32,
+    #[serde(rename = "number", default)]
+    pub chapterNumber: String,
+    pub files: Option<Vec<FileDto>>,
+    #[serde(default)]
+    pub avgHoursToRead: f64,
+    #[serde(default)]
+    pub isSpecial: bool,
+    /// Kavita's reading order within the volume.
+    #[serde(default)]
+    pub sortOrder: f32,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct FileDto {
+    pub filePath: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct SeriesDto {
+    #[serde(default)]
+    pub id: i32,
+    pub name: String,
+    pub coverImage: Option<String>,
+    pub libraryName: Option<String>,
+    #[serde(default)]
+    pub pages: i32,
+    #[serde(default)]
+    pub pagesRead: i32,
+    #[serde(default)]
+    pub format: MangaFormat,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct SeriesDetailDto {
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub volumes: Vec<VolumeDto>,
+    /// Chapters that aren't in a volume.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub storylineChapters: Vec<ChapterDto>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct VolumeDto {
+    pub id: i32,
+    pub number: i32,
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub chapters: Vec<ChapterDto>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SeriesMetadataDto {
+    #[serde(default)]
+    pub genres: Vec<TagDto>,
+    #[serde(default)]
+    pub tags: Vec<TagDto>,
+    #[serde(flatten)]
+    pub credits: CreditsDto,
+    pub language: Option<String>,
+    #[serde(default)]
+    pub ageRating: AgeRating,
+    /// Comma-separated URLs.
+    pub webLinks: Option<String>,
+}
+
+/// `/api/Series/chapter-metadata`. Only the credits are used; for comics and
+/// books they are often more precise than the series'.
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ChapterMetadataDto {
+    #[serde(flatten)]
+    pub credits: CreditsDto,
+}
+
+/// The people lists shared by series and chapter metadata.
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct CreditsDto {
+    #[serde(default)]
+    pub writers: Vec<PersonDto>,
+    #[serde(default)]
+    pub coverArtists: Vec<PersonDto>,
+    #[serde(default)]
+    pub pencillers: Vec<PersonDto>,
+    #[serde(default)]
+    pub inkers: Vec<PersonDto>,
+    #[serde(default)]
+    pub colorists: Vec<PersonDto>,
+    #[serde(default)]
+    pub letterers: Vec<PersonDto>,
+    #[serde(default)]
+    pub editors: Vec<PersonDto>,
+    #[serde(default)]
+    pub translators: Vec<PersonDto>,
+    #[serde(default)]
+    pub publishers: Vec<PersonDto>,
+}
+
+impl CreditsDto {
+    /// Everyone credited except publishers.
+    pub fn people(&self) -> impl Iterator<Item = &PersonDto> {
+        self.writers.iter()
+            .chain(&self.coverArtists)
+            .chain(&self.pencillers)
+            .chain(&self.inkers)
+            .chain(&self.colorists)
+            .chain(&self.letterers)
+            .chain(&self.editors)
+            .chain(&self.translators)
+    }
+}
+
+/// Used for both genres and tags, which share the same shape.
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct TagDto {
+    pub title: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct PersonDto {
+    pub name: String,
+}
+
+/// Kavita's `AppUserCollectionDto`.
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct CollectionDto {
+    pub title: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReadingListDto {
+    pub id: i32,
+    pub title: String,
+}
+
+/// One entry of a reading list, from `/api/ReadingList/items`.
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReadingListItemDto {
+    pub order: i32,
+    pub chapterId: i32,
+    pub seriesId: i32,
+    #[serde(default)]
+    pub pagesRead: i32,
+    #[serde(default)]
+    pub pagesTotal: i32,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct BookInfoDto {
+    pub volumeId: i32,
+    pub seriesName: String,
+    pub chapterNumber: String,
+    pub pages: i32,
+    pub chapterTitle: Option<String>,
+}
+
+/// `/api/Series/external-series-detail`, the series' Kavita+ match.
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct ExternalSeriesDto {
+    pub aniListId: Option<i32>,
+    pub malId: Option<i64>,
+}
+
+/// One entry of an EPUB's table of contents, from `/api/Book/{chapterId}/chapters`.
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct BookChapterItem {
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub title: String,
+    /// The spine page the entry starts on.
+    pub page: i32,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub children: Vec<BookChapterItem>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct HourEstimateRangeDto {
+    pub avgHours: f64,
+}
+
+/// Kavita's `LibraryType` enum, sent as an integer.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(from = "i32")]
+pub enum LibraryType {
+    Manga,
+    Comic,
+    Book,
+    Image,
+    LightNovel,
+    ComicVine,
+    Unknown,
+}
+
+impl From<i32> for LibraryType {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => LibraryType::Manga,
+            1 => LibraryType::Comic,
+            2 => LibraryType::Book,
+            3 => LibraryType::Image,
+            4 => LibraryType::LightNovel,
+            5 => LibraryType::ComicVine,
+            _ => LibraryType::Unknown,
+        }
+    }
+}
+
+impl LibraryType {
+    pub fn name(self) -> &'static str {
+        match self {
+            LibraryType::Manga => "manga",
+            LibraryType::Comic => "comic",
+            LibraryType::Book => "book",
+            LibraryType::Image => "image",
+            LibraryType::LightNovel => "light_novel",
+            LibraryType::ComicVine => "comic_vine",
+            LibraryType::Unknown => "unknown",
+        }
+    }
+}
+
+/// Kavita's `MangaFormat` enum, the file format of a series, sent as an integer.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(from = "i32")]
+pub enum MangaFormat {
+    Image,
+    Archive,
+    Epub,
+    Pdf,
+    #[default]
+    Unknown,
+}
+
+impl From<i32> for MangaFormat {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => MangaFormat::Image,
+            1 => MangaFormat::Archive,
+            3 => MangaFormat::Epub,
+            4 => MangaFormat::Pdf,
+            _ => MangaFormat::Unknown,
+        }
+    }
+}
+
+impl MangaFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            MangaFormat::Image => "image",
+            MangaFormat::Archive => "archive",
+            MangaFormat::Epub => "epub",
+            MangaFormat::Pdf => "pdf",
+            MangaFormat::Unknown => "unknown",
+        }
+    }
+}
+
+/// Kavita's `AgeRating` enum, sent as an integer. Higher values are for older readers.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(from = "i32")]
+pub enum AgeRating {
+    NotApplicable,
+    #[default]
+    Unknown,
+    RatingPending,
+    EarlyChildhood,
+    Everyone,
+    G,
+    Everyone10Plus,
+    PG,
+    KidsToAdults,
+    Teen,
+    Mature15Plus,
+    Mature17Plus,
+    Mature,
+    R18Plus,
+    AdultsOnly,
+    X18Plus,
+}
+
+const AGE_RATINGS: &[(AgeRating, &str)] = &[
+    (AgeRating::NotApplicable, "Not Applicable"),
+    (AgeRating::Unknown, "Unknown"),
+    (AgeRating::RatingPending, "Rating Pending"),
+    (AgeRating::EarlyChildhood, "Early Childhood"),
+    (AgeRating::Everyone, "Everyone"),
+    (AgeRating::G, "G"),
+    (AgeRating::Everyone10Plus, "Everyone 10+"),
+    (AgeRating::PG, "PG"),
+    (AgeRating::KidsToAdults, "Kids to Adults"),
+    (AgeRating::Teen, "Teen"),
+    (AgeRating::Mature15Plus, "MA15+"),
+    (AgeRating::Mature17Plus, "Mature 17+"),
+    (AgeRating::Mature, "M"),
+    (AgeRating::R18Plus, "R18+"),
+    (AgeRating::AdultsOnly, "Adults Only 18+"),
+    (AgeRating::X18Plus, "X18+"),
+];
+
+impl From<i32> for AgeRating {
+    fn from(value: i32) -> Self {
+        match value {
+            -1 => AgeRating::NotApplicable,
+            1 => AgeRating::RatingPending,
+            2..=14 => AGE_RATINGS[value as usize + 1].0,
+            _ => AgeRating::Unknown,
+        }
+    }
+}
+
+impl AgeRating {
+    /// The label Kavita shows for the rating.
+    pub fn name(self) -> &'static str {
+        AGE_RATINGS.iter()
+            .find(|(rating, _)| *rating == self)
+            .map_or("Unknown", |(_, name)| name)
+    }
+
+    /// Parses a rating as Kavita shows it (`Mature 17+`) or as spelled in its
+    /// API (`Mature17Plus`), ignoring case, spaces and punctuation.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let normalize = |s: &str| s.to_lowercase().replace('+', "plus")
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .collect::<String>();
+        let wanted = normalize(name);
+
+        AGE_RATINGS.iter()
+            .find(|(rating, label)| normalize(label) == wanted || normalize(&format!("{:?}", rating)) == wanted)
+            .map(|(rating, _)| *rating)
+    }
+
+    /// Ratings that say nothing about the content.
+    pub fn is_unknown(self) -> bool {
+        matches!(self, AgeRating::NotApplicable | AgeRating::Unknown | AgeRating::RatingPending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn age_ratings_follow_kavitas_numbering() {
+        let cases = [
+            (-1, AgeRating::NotApplicable),
+            (0, AgeRating::Unknown),
+            (1, AgeRating::RatingPending),
+            (2, AgeRating::EarlyChildhood),
+            (8, AgeRating::Teen),
+            (10, AgeRating::Mature17Plus),
+            (14, AgeRating::X18Plus),
+            (15, AgeRating::Unknown),
+            (-2, AgeRating::Unknown),
+        ];
+        for (value, rating) in cases {
+            assert_eq!(AgeRating::from(value), rating, "{}", value);
+        }
+    }
+
+    #[test]
+    fn age_ratings_are_ordered_by_audience() {
+        assert!(AgeRating::from(14) > AgeRating::from(8));
+        assert!(AgeRating::Teen > AgeRating::Everyone10Plus);
+        assert!(AgeRating::Mature17Plus > AgeRating::Mature15Plus);
+    }
+
+    #[test]
+    fn age_rating_names_match_kavitas_labels_and_api_spelling() {
+        assert_eq!(AgeRating::from_name("Mature 17+"), Some(AgeRating::Mature17Plus));
+        assert_eq!(AgeRating::from_name("mature17plus"), Some(AgeRating::Mature17Plus));
+        assert_eq!(AgeRating::from_name("Everyone 10+"), Some(AgeRating::Everyone10Plus));
+        assert_eq!(AgeRating::from_name("pg"), Some(AgeRating::PG));
+        assert_eq!(AgeRating::from_name("Spicy"), None);
+        assert_eq!(AgeRating::Mature15Plus.name(), "MA15+");
+    }
+
+    #[test]
+    fn null_strings_and_lists_read_as_empty() {
+        let toc: Vec<BookChapterItem> = serde_json::from_value(serde_json::json!([
+            { "title": null, "page": 1, "children": null },
+            { "page": 2 },
+        ])).unwrap();
+        assert_eq!((toc[0].title.as_str(), toc[0].children.len()), ("", 0));
+        assert_eq!((toc[1].title.as_str(), toc[1].children.len()), ("", 0));
+
+        let info: ChapterInfoDto = serde_json::from_value(serde_json::json!({
+            "chapterNumber": null,
+            "volumeNumber": null,
+            "seriesName": null,
+            "libraryType": 0,
+            "chapterTitle": null,
+            "pages": 20,
+        })).unwrap();
+        assert_eq!((info.chapterNumber, info.volumeNumber, info.seriesName), Default::default());
+
+        let detail: SeriesDetailDto = serde_json::from_value(serde_json::json!({
+            "volumes": [{ "id": 1, "number": 1, "name": null, "chapters": null }],
+            "storylineChapters": null,
+        })).unwrap();
+        assert!(detail.volumes[0].chapters.is_empty());
+        assert!(detail.storylineChapters.is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+//! In-memory [`KavitaApi`] for exercising presence logic without a server.
+
+use std::collections::HashMap;
+
+use super::{
+    ApiResult, BookChapterItem, BookInfoDto, ChapterDto, ChapterInfoDto, ChapterMetadataDto,
+    CollectionDto, ExternalSeriesDto, FullProgressDto, HourEstimateRangeDto, KavitaApi, LibraryType,
+    ProgressDto, ReadHistoryEvent, ReadingListDto, ReadingListItemDto, SeriesDetailDto, SeriesDto,
+    SeriesMetadataDto, StatusError,
+};
+
+/// Every lookup reads from the public maps; a missing entry is reported as an error,
+/// the same way a 404 from the real server would be. `errors` makes a method fail
+/// with the given status instead, keyed by the method's name.
+#[derive(Debug, Default)]
+pub struct FakeKavita {
+    pub user_id: i32,
+    pub history: Vec<ReadHistoryEvent>,
+    pub progress: HashMap<i32, ProgressDto>,
+    pub chapter_progress: HashMap<i32, Vec<FullProgressDto>>,
+    pub chapter_infos: HashMap<i32, ChapterInfoDto>,
+    pub chapters: HashMap<i32, ChapterDto>,
+    pub series: HashMap<i32, SeriesDto>,
+    pub series_details: HashMap<i32, SeriesDetailDto>,
+    pub series_metadata: HashMap<i32, SeriesMetadataDto>,
+    pub chapter_metadata: HashMap<i32, ChapterMetadataDto>,
+    pub external_series: HashMap<i32, ExternalSeriesDto>,
+    pub book_info: HashMap<i32, BookInfoDto>,
+    pub book_chapters: HashMap<i32, Vec<BookChapterItem>>,
+    pub time_left: HashMap<i32, HourEstimateRangeDto>,
+    pub latest_chapters: HashMap<i32, ChapterDto>,
+    pub on_deck: Vec<SeriesDto>,
+    pub continue_points: HashMap<i32, ChapterDto>,
+    /// The chapter after each chapter id; missing ones are the last in their series.
+    pub next_chapters: HashMap<i32, i32>,
+    pub collections: Vec<CollectionDto>,
+    pub series_collections: HashMap<i32, Vec<CollectionDto>>,
+    pub series_reading_lists: HashMap<i32, Vec<ReadingListDto>>,
+    pub chapter_reading_lists: HashMap<i32, Vec<ReadingListDto>>,
+    pub reading_list_items: HashMap<i32, Vec<ReadingListItemDto>>,
+    /// Series whose library allows scrobbling.
+    pub scrobbling_series: Vec<i32>,
+    pub scrobble_holds: Vec<i32>,
+    pub library_types: HashMap<i32, LibraryType>,
+    pub base_url: String,
+    pub errors: HashMap<&'static str, reqwest::StatusCode>,
+}
+
+impl FakeKavita {
+    fn fail(&self, method: &str) -> ApiResult<()> {
+        match self.errors.get(method) {
+            Some(status) => Err(Box::new(StatusError { endpoint: method.to_string(), status: *status })),
+            None => Ok(()),
+        }
+    }
+}
+
+fn lookup<T: Clone>(map: &HashMap<i32, T>, what: &str, id: i32) -> ApiResult<T> {
+    map.get(&id)
+        .cloned()
+        .ok_or_else(|| format!("fake Kavita has no {} {}", what, id).into())
+}
+
+impl KavitaApi for FakeKavita {
+    async fn current_user_id(&self) -> ApiResult<i32> {
+        self.fail("current_user_id")?;
+        Ok(self.user_id)
+    }
+
+    async fn reading_history(&self, _user_id: i32) -> ApiResult<Vec<ReadHistoryEvent>> {
+        self.fail("reading_history")?;
+        Ok(self.history.clone())
+    }
+
+    async fn progress(&self, chapter_id: i32) -> ApiResult<ProgressDto> {
+        self.fail("progress")?;
+        lookup(&self.progress, "progress for chapter", chapter_id)
+    }
+
+    async fn chapter_progress(&self, chapter_id: i32) -> ApiResult<Vec<FullProgressDto>> {
+        self.fail("chapter_progress")?;
+        Ok(self.chapter_progress.get(&chapter_id).cloned().unwrap_or_default())
+    }
+
+    async fn chapter_info(&self, chapter_id: i32) -> ApiResult<ChapterInfoDto> {
+        self.fail("chapter_info")?;
+        lookup(&self.chapter_infos, "chapter info", chapter_id)
+    }
+
+    async fn chapter(&self, chapter_id: i32) -> ApiResult<ChapterDto> {
+        self.fail("chapter")?;
+        lookup(&self.chapters, "chapter", chapter_id)
+    }
+
+    async fn series(&self, series_id: i32) -> ApiResult<SeriesDto> {
+        self.fail("series")?;
+        lookup(&self.series, "series", series_id)
+    }
+
+    async fn series_detail(&self, series_id: i32) -> ApiResult<SeriesDetailDto> {
+        self.fail("series_detail")?;
+        lookup(&self.series_details, "series detail", series_id)
+    }
+
+    async fn series_metadata(&self, series_id: i32) -> ApiResult<SeriesMetadataDto> {
+        self.fail("series_metadata")?;
+        lookup(&self.series_metadata, "series metadata", series_id)
+    }
+
+    async fn chapter_metadata(&self, chapter_id: i32) -> ApiResult<ChapterMetadataDto> {
+        self.fail("chapter_metadata")?;
+        lookup(&self.chapter_metadata, "chapter metadata", chapter_id)
+    }
+
+    async fn external_series(&self, series_id: i32) -> ApiResult<ExternalSeriesDto> {
+        self.fail("external_series")?;
+        lookup(&self.external_series, "external series for series", series_id)
+    }
+
+    async fn book_info(&self, chapter_id: i32) -> ApiResult<BookInfoDto> {
+        self.fail("book_info")?;
+        lookup(&self.book_info, "book info for chapter", chapter_id)
+    }
+
+    async fn book_chapters(&self, chapter_id: i32) -> ApiResult<Vec<BookChapterItem>> {
+        self.fail("book_chapters")?;
+        lookup(&self.book_chapters, "table of contents for chapter", chapter_id)
+    }
+
+    async fn time_left(&self, series_id: i32) -> ApiResult<HourEstimateRangeDto> {
+        self.fail("time_left")?;
+        lookup(&self.time_left, "time estimate for series", series_id)
+    }
+
+    async fn latest_chapter(&self, series_id: i32) -> ApiResult<Option<ChapterDto>> {
+        self.fail("latest_chapter")?;
+        Ok(self.latest_chapters.get(&series_id).cloned())
+    }
+
+    async fn on_deck(&self, count: usize) -> ApiResult<Vec<SeriesDto>> {
+        self.fail("on_deck")?;
+        Ok(self.on_deck.iter().take(count).cloned().collect())
+    }
+
+    async fn continue_point(&self, series_id: i32) -> ApiResult<ChapterDto> {
+        self.fail("continue_point")?;
+        lookup(&self.continue_points, "continue point for series", series_id)
+    }
+
+    async fn next_chapter(&self, _series_id: i32, _volume_id: i32, chapter_id: i32) -> ApiResult<Option<i32>> {
+        self.fail("next_chapter")?;
+        Ok(self.next_chapters.get(&chapter_id).copied())
+    }
+
+    async fn collections(&self) -> ApiResult<Vec<CollectionDto>> {
+        self.fail("collections")?;
+        Ok(self.collections.clone())
+    }
+
+    async fn series_collections(&self, series_id: i32) -> ApiResult<Vec<CollectionDto>> {
+        self.fail("series_collections")?;
+        Ok(self.series_collections.get(&series_id).cloned().unwrap_or_default())
+    }
+
+    async fn series_reading_lists(&self, series_id: i32) -> ApiResult<Vec<ReadingListDto>> {
+        self.fail("series_reading_lists")?;
+        Ok(self.series_reading_lists.get(&series_id).cloned().unwrap_or_default())
+    }
+
+    async fn chapter_reading_lists(&self, chapter_id: i32) -> ApiResult<Vec<ReadingListDto>> {
+        self.fail("chapter_reading_lists")?;
+        Ok(self.chapter_reading_lists.get(&chapter_id).cloned().unwrap_or_default())
+    }
+
+    async fn reading_list_items(&self, reading_list_id: i32) -> ApiResult<Vec<ReadingListItemDto>> {
+        self.fail("reading_list_items")?;
+        lookup(&self.reading_list_items, "reading list", reading_list_id)
+    }
+
+    async fn library_allows_scrobbling(&self, series_id: i32) -> ApiResult<bool> {
+        self.fail("library_allows_scrobbling")?;
+        Ok(self.scrobbling_series.contains(&series_id))
+    }
+
+    async fn has_scrobble_hold(&self, series_id: i32) -> ApiResult<bool> {
+        self.fail("has_scrobble_hold")?;
+        Ok(self.scrobble_holds.contains(&series_id))
+    }
+
+    async fn server_base_url(&self) -> ApiResult<String> {
+        self.fail("server_base_url")?;
+        Ok(self.base_url.clone())
+    }
+
+    async fn library_type(&self, library_id: i32) -> ApiResult<LibraryType> {
+        self.fail("library_type")?;
+        lookup(&self.library_types, "library", library_id)
+    }
+}
@@ -0,0 +1,127 @@
+//! Typed access to the parts of the Kavita REST API this tool uses.
+
+mod client;
+mod dto;
+#[cfg(test)]
+pub mod fake;
+
+pub use client::KavitaClient;
+pub use dto::*;
+
+use std::fmt;
+
+pub type ApiResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// One method per Kavita endpoint. Implemented over HTTP by [`KavitaClient`]
+/// and in memory by [`fake::FakeKavita`].
+pub trait KavitaApi {
+    /// `/api/Users/myself`
+    async fn current_user_id(&self) -> ApiResult<i32>;
+    /// `/api/Stats/user/reading-history`
+    async fn reading_history(&self, user_id: i32) -> ApiResult<Vec<ReadHistoryEvent>>;
+    /// `/api/Reader/get-progress`
+    async fn progress(&self, chapter_id: i32) -> ApiResult<ProgressDto>;
+    /// `/api/Reader/all-chapter-progress`
+    async fn chapter_progress(&self, chapter_id: i32) -> ApiResult<Vec<FullProgressDto>>;
+    /// `/api/Reader/chapter-info`
+    async fn chapter_info(&self, chapter_id: i32) -> ApiResult<ChapterInfoDto>;
+    /// `/api/Chapter`
+    async fn chapter(&self, chapter_id: i32) -> ApiResult<ChapterDto>;
+    /// `/api/Series/{seriesId}`
+    async fn series(&self, series_id: i32) -> ApiResult<SeriesDto>;
+    /// `/api/Series/series-detail`
+    async fn series_detail(&self, series_id: i32) -> ApiResult<SeriesDetailDto>;
+    /// `/api/Series/metadata`
+    async fn series_metadata(&self, series_id: i32) -> ApiResult<SeriesMetadataDto>;
+    /// `/api/Series/chapter-metadata`
+    async fn chapter_metadata(&self, chapter_id: i32) -> ApiResult<ChapterMetadataDto>;
+    /// `/api/Series/external-series-detail`, only answered with Kavita+
+    async fn external_series(&self, series_id: i32) -> ApiResult<ExternalSeriesDto>;
+    /// `/api/Book/{chapterId}/book-info`
+    async fn book_info(&self, chapter_id: i32) -> ApiResult<BookInfoDto>;
+    /// `/api/Book/{chapterId}/chapters`
+    async fn book_chapters(&self, chapter_id: i32) -> ApiResult<Vec<BookChapterItem>>;
+    /// `/api/Reader/time-left`
+    async fn time_left(&self, series_id: i32) -> ApiResult<HourEstimateRangeDto>;
+    /// `/api/Tachiyomi/latest-chapter`, `None` when nothing in the series has been read
+    async fn latest_chapter(&self, series_id: i32) -> ApiResult<Option<ChapterDto>>;
+    /// `/api/Series/on-deck`, the first `count` series with progress in them
+    async fn on_deck(&self, count: usize) -> ApiResult<Vec<SeriesDto>>;
+    /// `/api/Reader/continue-point`, the chapter to carry on reading a series from
+    async fn continue_point(&self, series_id: i32) -> ApiResult<ChapterDto>;
+    /// `/api/Reader/next-chapter`, `None` after the last chapter
+    async fn next_chapter(&self, series_id: i32, volume_id: i32, chapter_id: i32) -> ApiResult<Option<i32>>;
+    /// `/api/Collection`, including promoted collections
+    async fn collections(&self) -> ApiResult<Vec<CollectionDto>>;
+    /// `/api/Collection/all-series`, the collections containing a series
+    async fn series_collections(&self, series_id: i32) -> ApiResult<Vec<CollectionDto>>;
+    /// `/api/ReadingList/lists-for-series`
+    async fn series_reading_lists(&self, series_id: i32) -> ApiResult<Vec<ReadingListDto>>;
+    /// `/api/ReadingList/lists-for-chapter`
+    async fn chapter_reading_lists(&self, chapter_id: i32) -> ApiResult<Vec<ReadingListDto>>;
+    /// `/api/ReadingList/items`
+    async fn reading_list_items(&self, reading_list_id: i32) -> ApiResult<Vec<ReadingListItemDto>>;
+    /// `/api/Scrobbling/library-allows-scrobbling`
+    async fn library_allows_scrobbling(&self, series_id: i32) -> ApiResult<bool>;
+    /// `/api/Scrobbling/has-hold`
+    async fn has_scrobble_hold(&self, series_id: i32) -> ApiResult<bool>;
+    /// `/api/Settings/base-url`, the path Kavita is served under, such as `/kavita/`
+    async fn server_base_url(&self) -> ApiResult<String>;
+    /// `/api/Library/type`
+    async fn library_type(&self, library_id: i32) -> ApiResult<LibraryType>;
+}
+
+/// Kavita answered with a non-success status code.
+#[derive(Debug)]
+pub struct StatusError {
+    pub endpoint: String,
+    pub status: reqwest::StatusCode,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} returned {}", self.endpoint, self.status)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Whether the error means the JWT was rejected and the session should be renewed.
+pub fn is_unauthorized(error: &(dyn std::error::Error + 'static)) -> bool {
+    has_status(error, reqwest::StatusCode::UNAUTHORIZED)
+}
+
+/// Whether the server answered that what was asked for doesn't exist.
+pub fn is_not_found(error: &(dyn std::error::Error + 'static)) -> bool {
+    has_status(error, reqwest::StatusCode::NOT_FOUND)
+}
+
+fn has_status(error: &(dyn std::error::Error + 'static), status: reqwest::StatusCode) -> bool {
+    error.downcast_ref::<StatusError>().is_some_and(|e| e.status == status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::FakeKavita;
+    use super::*;
+
+    #[tokio::test]
+    async fn only_a_401_asks_for_a_new_session() {
+        let kavita = FakeKavita {
+            errors: [("chapter", reqwest::StatusCode::UNAUTHORIZED)].into(),
+            ..Default::default()
+        };
+        let error = kavita.chapter(1).await.unwrap_err();
+        assert!(is_unauthorized(error.as_ref()));
+
+        let error = kavita.series(1).await.unwrap_err();
+        assert!(!is_unauthorized(error.as_ref()), "a missing series isn't an auth failure");
+
+        let kavita = FakeKavita {
+            errors: [("series", reqwest::StatusCode::NOT_FOUND)].into(),
+            ..Default::default()
+        };
+        let error = kavita.series(1).await.unwrap_err();
+        assert!(!is_unauthorized(error.as_ref()));
+    }
+}
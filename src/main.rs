@@ -1,25 +1,59 @@
 #[macro_use]
 extern crate lazy_static;
 
+mod buttons;
+mod context;
+mod cover;
+mod credits;
+mod detect;
+mod kavita;
+mod membership;
+mod presence;
+mod privacy;
+mod reading_list;
+mod schedule;
+mod session;
+mod signalr;
+mod speed;
+mod spoilers;
+mod template;
+
 use discord_rich_presence::{activity, DiscordIpcClient, DiscordIpc};
 use serde::Deserialize;
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tokio::time;
 use reqwest::Client;
 use std::env;
 use std::time::{SystemTime, UNIX_EPOCH};
 use log::{info, error, warn};
-use env_logger;
-use chrono;
 use std::cmp::Ordering;
 use semver::Version;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
+use buttons::LinkResolver;
+use context::ResolveCache;
+use cover::CoverEffect;
+use detect::ActivityDetector;
+use kavita::KavitaClient;
+use membership::ListCache;
+use presence::PresenceModel;
+use reading_list::ReadingListTracker;
+use session::Session;
+use signalr::{EventHub, HubEvent};
+use speed::SpeedModel;
 
 const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// How often Kavita is polled when the SignalR hub isn't connected.
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Polling still runs while the hub is connected, to notice inactivity and
+/// reading done outside the hub's view.
+const HUB_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
 struct ImageCache {
     urls: HashMap<String, (String, Instant)>,
     max_age: Duration,
@@ -56,9 +90,11 @@ struct Config {
     discord_client_id: String,
     kavita_url: String,
     kavita_api_key: String,
-    kavita_username: String,
-    kavita_password: String,
+    kavita_username: Option<String>,
+    kavita_password: Option<String>,
     show_page_numbers: Option<bool>,
+    author_roles: Option<Vec<credits::Role>>,
+    author_from_path: Option<bool>,
     blacklisted_series_ids: Option<Vec<i32>>,
     blacklisted_series_names: Option<Vec<String>>,
     blacklisted_tags: Option<Vec<String>>,
@@ -67,52 +103,34 @@ struct Config {
     inactivity_timeout_minutes: Option<u64>,
     image_format: Option<String>,
     proxy_enabled: Option<bool>,
+    details_template: Option<String>,
+    state_template: Option<String>,
+    large_text_template: Option<String>,
+    small_image: Option<String>,
+    small_text_template: Option<String>,
+    timestamp_mode: Option<presence::TimestampMode>,
+    reading_speed_file: Option<String>,
+    signalr_enabled: Option<bool>,
+    detectors: Option<Vec<detect::Detector>>,
+    privacy: Option<privacy::PrivacyConfig>,
+    spoilers: Option<spoilers::SpoilerConfig>,
+    schedule: Option<schedule::ScheduleConfig>,
+    reading_list_context: Option<bool>,
+    buttons: Option<buttons::ButtonConfig>,
 }
 
-#[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
-struct ReadHistoryEvent {
-    seriesId: i32,
-    seriesName: String,
-    readDate: String,
-    readDateUtc: String,
-    chapterId: i32,
-}
-
-#[allow(non_snake_case)]
-#[derive(Debug, Deserialize, Clone)]
-struct ProgressDto {
-    chapterId: i32,
-    pageNum: i32,
-    libraryId: i32,
-}
-
-#[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
-struct ChapterDto {
-    id: i32,
-    range: String,
-    title: Option<String>,
-    pages: i32,
-    coverImage: Option<String>,
-    volumeId: i32,
-    #[serde(rename = "number", default)]
-    chapterNumber: String,
-    files: Option<Vec<FileDto>>,
-}
-
-#[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
-struct FileDto {
-    filePath: String,
-}
-
-#[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
-struct SeriesDto {
-    id: i32,
-    name: String,
-    coverImage: Option<String>,
+/// A config with only the required keys, plus `overrides`.
+#[cfg(test)]
+fn test_config(overrides: serde_json::Value) -> Config {
+    let mut config = serde_json::json!({
+        "discord_client_id": "0",
+        "kavita_url": "https://kavita.example.com",
+        "kavita_api_key": "key",
+    });
+    if let (Some(config), serde_json::Value::Object(overrides)) = (config.as_object_mut(), overrides) {
+        config.extend(overrides);
+    }
+    serde_json::from_value(config).expect("test config should deserialize")
 }
 
 #[derive(Debug)]
@@ -127,39 +145,16 @@ struct ReadingState {
     is_reading: bool,
     current_page: i32,
     total_pages: i32,
-}
-
-#[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
-struct UserDto {
-    username: Option<String>,
-    token: Option<String>,
-}
-
-#[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
-struct BookInfoDto {
-    seriesId: i32,
-    volumeId: i32,
-    seriesName: String,
-    chapterNumber: String,
-    pages: i32,
-    chapterTitle: Option<String>,
-}
-
-#[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
-struct SeriesDetailDto {
-    specials: Vec<ChapterDto>,
-    volumes: Vec<VolumeDto>,
-}
-
-#[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
-struct VolumeDto {
-    id: i32,
-    number: i32,
-    name: Option<String>,
+    /// When the current reading session began, see [`continues_session`].
+    session_start: SystemTime,
+    /// The series read in the current session.
+    session_series: Option<i32>,
+    speed: SpeedModel,
+    detector: ActivityDetector,
+    lists: ListCache,
+    reading_list: ReadingListTracker,
+    links: LinkResolver,
+    resolved: ResolveCache,
 }
 
 #[tokio::main]
@@ -176,6 +171,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     
     let config = load_config(&config_file)?;
+    presence::warn_unknown_placeholders(&config);
     
     let mut discord = DiscordIpcClient::new(&config.discord_client_id);
     discord.connect()?;
@@ -187,13 +183,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         is_reading: false,
         current_page: 0,
         total_pages: 0,
+        session_start: SystemTime::now(),
+        session_series: None,
+        speed: SpeedModel::load(speed_file_path(&config, &config_file)),
+        detector: ActivityDetector::default(),
+        lists: ListCache::default(),
+        reading_list: ReadingListTracker::default(),
+        links: LinkResolver::default(),
+        resolved: ResolveCache::default(),
     };
     let mut current_book: Option<Book> = None;
+    let mut session = Session::new();
+    let mut hub = EventHub::new();
     
     loop {
         if let Err(e) = update_discord_status(
             &client,
             &config,
+            &mut session,
             &mut discord,
             &mut reading_state,
             &mut current_book,
@@ -207,7 +214,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 info!("Successfully reconnected to Discord");
             }
         }
-        time::sleep(Duration::from_secs(15)).await;
+        
+        if config.signalr_enabled.unwrap_or(true) && hub.should_connect() {
+            match session.token(&client, &config).await {
+                Ok(token) => hub.connect(&config.kavita_url, &token),
+                Err(e) => warn!("Not connecting to the Kavita SignalR hub: {}", e),
+            }
+        }
+        
+        let interval = if hub.is_connected() { HUB_POLL_INTERVAL } else { POLL_INTERVAL };
+        tokio::select! {
+            _ = time::sleep(interval) => {},
+            event = hub.next_progress() => {
+                if let HubEvent::ProgressUpdate { series_id, chapter_id, pages_read } = event {
+                    info!("Kavita saved progress (series {}, chapter {}, page {}), refreshing presence",
+                          series_id, chapter_id, pages_read);
+                }
+            }
+        }
     }
 }
 
@@ -224,28 +248,35 @@ fn parse_args() -> Result<String, Box<dyn std::error::Error>> {
     }
 }
 
+/// Where the learned reading speed is kept: `reading_speed_file`, or next to the config file.
+fn speed_file_path(config: &Config, config_file: &str) -> PathBuf {
+    match &config.reading_speed_file {
+        Some(path) => PathBuf::from(path),
+        None => Path::new(config_file).with_file_name("reading_speed.json"),
+    }
+}
+
 fn load_config(config_file: &str) -> Result<Config, Box<dyn std::error::Error>> {
     let config_str = fs::read_to_string(config_file)?;
-    let config: Config = serde_json::from_str(&config_str)?;
+    let mut config: Config = serde_json::from_str(&config_str)?;
+    privacy::migrate_blacklists(&mut config);
     Ok(config)
 }
 
 async fn update_discord_status(
     client: &Client,
     config: &Config,
+    session: &mut Session,
     discord: &mut DiscordIpcClient,
     reading_state: &mut ReadingState,
     current_book: &mut Option<Book>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    match check_kavita_server(client, config).await {
-        Err(e) => {
-            info!("Kavita server unreachable: {}. Clearing Discord status.", e);
-            discord.clear_activity()?;
-            reading_state.is_reading = false;
-            *current_book = None;
-            return Ok(());
-        },
-        Ok(_) => {}
+    if let Err(e) = check_kavita_server(client, config).await {
+        info!("Kavita server unreachable: {}. Clearing Discord status.", e);
+        discord.clear_activity()?;
+        reading_state.is_reading = false;
+        *current_book = None;
+        return Ok(());
     }
     
     if reading_state.is_reading {
@@ -280,558 +311,105 @@ async fn update_discord_status(
         }
     }
     
-    let login_url = format!("{}/api/Account/login", config.kavita_url);
-    info!("Logging in to Kavita at: {}", login_url);
-    
-    let login_data = serde_json::json!({
-        "username": config.kavita_username,
-        "password": config.kavita_password
-    });
-    
-    let login_response = client
-        .post(&login_url)
-        .json(&login_data)
-        .send()
-        .await?;
-        
-    if !login_response.status().is_success() {
-        error!("Login failed: {}", login_response.status());
-        let error_text = login_response.text().await?;
-        error!("Login error: {}", error_text);
-        return Ok(());
-    }
-    
-    let user_data: UserDto = login_response.json().await?;
-    let jwt_token = user_data.token.ok_or("JWT token not found in login response")?;
-    info!("Successfully logged in as {}", user_data.username.unwrap_or_default());
+    let jwt_token = match session.token(client, config).await {
+        Ok(token) => token,
+        Err(e) => {
+            error!("Failed to authenticate with Kavita: {}", e);
+            return Ok(());
+        }
+    };
     
-    match check_current_progress(client, config, &jwt_token).await {
-        Ok(Some((progress, series_id, _format, series_name))) => {
-            if let Some(blacklisted_library_ids) = &config.blacklisted_library_ids {
-                if blacklisted_library_ids.contains(&progress.libraryId) {
-                    info!("Library ID {} is blacklisted, not updating Discord status", progress.libraryId);
-                    if reading_state.is_reading {
-                        if let Err(e) = discord.clear_activity() {
-                            error!("Failed to clear Discord activity: {}", e);
-                        } else {
-                            reading_state.is_reading = false;
-                            info!("Cleared Discord status due to blacklisted library");
-                        }
-                    }
-                    return Ok(());
-                }
-            }
+    let api = KavitaClient::new(client, &config.kavita_url, &jwt_token);
+    
+    match reading_state.detector.detect(&api, config).await {
+        Ok(Some((progress, series_id, series_name))) => {
+            let resolved = context::resolve(
+                &api,
+                config,
+                &mut reading_state.resolved,
+                &reading_state.speed,
+                &progress,
+                series_id,
+                &series_name,
+            ).await;
+            let mut context = match resolved {
+                Some(context) => context,
+                None => return Ok(()),
+            };
+            context.reading_list = reading_state.reading_list.lookup(&api, config, context.chapter_id).await;
             
-            if let Some(blacklisted_ids) = &config.blacklisted_series_ids {
-                if blacklisted_ids.contains(&series_id) {
-                    info!("Series ID {} is blacklisted, not updating Discord status", series_id);
-                    if reading_state.is_reading {
-                        if let Err(e) = discord.clear_activity() {
-                            error!("Failed to clear Discord activity: {}", e);
-                        } else {
-                            reading_state.is_reading = false;
-                            info!("Cleared Discord status due to blacklisted series");
-                        }
-                    }
-                    return Ok(());
-                }
+            let mut decision = reading_state.lists.decide(&api, config, &context).await;
+            if let Some(scheduled) = schedule::evaluate(config, &context) {
+                decision.action = decision.action.stricter(scheduled);
             }
-            
-            if let Some(blacklisted_names) = &config.blacklisted_series_names {
-                if blacklisted_names.iter().any(|name| series_name.contains(name)) {
-                    info!("Series '{}' matches blacklisted name, not updating Discord status", series_name);
-                    if reading_state.is_reading {
-                        if let Err(e) = discord.clear_activity() {
-                            error!("Failed to clear Discord activity: {}", e);
-                        } else {
-                            reading_state.is_reading = false;
-                            info!("Cleared Discord status due to blacklisted series");
-                        }
-                    }
-                    return Ok(());
-                }
-            }
-            
-            if config.blacklisted_tags.is_some() || config.blacklisted_genres.is_some() {
-                let metadata_url = format!(
-                    "{}/api/Series/metadata?seriesId={}",
-                    config.kavita_url, series_id
-                );
-                
-                info!("Getting series metadata from: {}", metadata_url);
-                
-                let metadata_response = client
-                    .get(&metadata_url)
-                    .header("Authorization", format!("Bearer {}", jwt_token))
-                    .send()
-                    .await;
-                    
-                match metadata_response {
-                    Ok(response) if response.status().is_success() => {
-                        match response.json::<serde_json::Value>().await {
-                            Ok(metadata) => {
-                                if let Some(blacklisted_tags) = &config.blacklisted_tags {
-                                    if let Some(tags) = metadata.get("tags").and_then(|t| t.as_array()) {
-                                        for tag in tags {
-                                            if let Some(tag_name) = tag.get("title").and_then(|t| t.as_str()) {
-                                                if blacklisted_tags.iter().any(|bt| tag_name.to_lowercase().contains(&bt.to_lowercase())) {
-                                                    info!("Series contains blacklisted tag: '{}', not updating Discord status", tag_name);
-                                                    if reading_state.is_reading {
-                                                        if let Err(e) = discord.clear_activity() {
-                                                            error!("Failed to clear Discord activity: {}", e);
-                                                        } else {
-                                                            reading_state.is_reading = false;
-                                                            info!("Cleared Discord status due to blacklisted tag");
-                                                        }
-                                                    }
-                                                    return Ok(());
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                
-                                if let Some(blacklisted_genres) = &config.blacklisted_genres {
-                                    if let Some(genres) = metadata.get("genres").and_then(|g| g.as_array()) {
-                                        for genre in genres {
-                                            if let Some(genre_name) = genre.get("title").and_then(|g| g.as_str()) {
-                                                if blacklisted_genres.iter().any(|bg| genre_name.to_lowercase().contains(&bg.to_lowercase())) {
-                                                    info!("Series contains blacklisted genre: '{}', not updating Discord status", genre_name);
-                                                    if reading_state.is_reading {
-                                                        if let Err(e) = discord.clear_activity() {
-                                                            error!("Failed to clear Discord activity: {}", e);
-                                                        } else {
-                                                            reading_state.is_reading = false;
-                                                            info!("Cleared Discord status due to blacklisted genre");
-                                                        }
-                                                    }
-                                                    return Ok(());
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            },
-                            Err(e) => {
-                                error!("Failed to parse series metadata: {}", e);
-                            }
-                        }
-                    },
-                    Ok(response) => {
-                        error!("Failed to get series metadata: {}", response.status());
-                    },
-                    Err(e) => {
-                        error!("Error fetching series metadata: {}", e);
+            if decision.action == privacy::Action::Deny {
+                if reading_state.is_reading {
+                    if let Err(e) = discord.clear_activity() {
+                        error!("Failed to clear Discord activity: {}", e);
+                    } else {
+                        reading_state.is_reading = false;
+                        info!("Cleared Discord status due to privacy rules or schedule");
                     }
                 }
-            }
-            
-            let chapter_url = format!(
-                "{}/api/Chapter?chapterId={}",
-                config.kavita_url, progress.chapterId
-            );
-            
-            info!("Getting chapter details from: {}", chapter_url);
-            
-            let chapter_response = client
-                .get(&chapter_url)
-                .header("Authorization", format!("Bearer {}", jwt_token))
-                .send()
-                .await?;
-                
-            if !chapter_response.status().is_success() {
-                error!("Failed to get chapter details: {}", chapter_response.status());
                 return Ok(());
             }
             
-            let chapter_text = chapter_response.text().await?;
-            let chapter: ChapterDto = match serde_json::from_str::<ChapterDto>(&chapter_text) {
-                Ok(ch) => {
-                    if !ch.chapterNumber.contains("-100000") {
-                        info!("DEBUG - Manga chapter - volume ID: {}, range: {}, chapterNumber: {}", 
-                              ch.volumeId, ch.range, ch.chapterNumber);
-                        
-                        let volume_number = ch.range.split('-').next()
-                            .and_then(|s| s.trim().parse::<f32>().ok())
-                            .map(|n| n.floor() as i32);
-                        info!("DEBUG - Extracted volume number from range: {:?}", volume_number);
-                    }
-                    
-                    ch
-                },
-                Err(e) => {
-                    error!("Failed to parse chapter details: {}", e);
-                    error!("Raw response: {}", chapter_text);
-                    
-                    let book_url = format!(
-                        "{}/api/book/{}/book-info",
-                        config.kavita_url, progress.chapterId
-                    );
-                    
-                    match client
-                        .get(&book_url)
-                        .header("Authorization", format!("Bearer {}", jwt_token))
-                        .send()
-                        .await
-                    {
-                        Ok(book_resp) if book_resp.status().is_success() => {
-                            match book_resp.json::<BookInfoDto>().await {
-                                Ok(book_info) => {
-                                    ChapterDto {
-                                        id: progress.chapterId,
-                                        range: book_info.seriesName.clone(),
-                                        title: book_info.chapterTitle.clone(),
-                                        pages: book_info.pages,
-                                        coverImage: None,
-                                        volumeId: book_info.volumeId,
-                                        chapterNumber: book_info.chapterNumber.clone(),
-                                        files: None,
-                                    }
-                                },
-                                Err(_) => {
-                                    return Ok(());
-                                }
-                            }
-                        },
-                        _ => {
-                            return Ok(());
-                        }
-                    }
-                }
-            };
+            let new_book = current_book.as_ref().is_none_or(|book| {
+                book.series_id != context.series_id || book.chapter_id != context.chapter_id
+            });
             
-            let series_url = format!(
-                "{}/api/Series/series-detail?seriesId={}",
-                config.kavita_url, series_id
+            let inactivity_timeout = Duration::from_secs(
+                config.inactivity_timeout_minutes.unwrap_or(30) * 60
             );
-            
-            info!("Getting series details from: {}", series_url);
-            
-            let series_response = client
-                .get(&series_url)
-                .header("Authorization", format!("Bearer {}", jwt_token))
-                .send()
-                .await?;
-                
-            if !series_response.status().is_success() {
-                error!("Failed to get series details: {}", series_response.status());
-                return Ok(());
+            let now = SystemTime::now();
+            let (series, last_active) = (reading_state.session_series, reading_state.last_api_time);
+            if !continues_session(series, last_active, context.series_id, now, inactivity_timeout) {
+                info!("Starting a new reading session");
+                reading_state.session_start = now;
             }
+            reading_state.session_series = Some(context.series_id);
             
-            let series_text = series_response.text().await?;
-
-
-            let series: SeriesDto = match serde_json::from_str::<SeriesDto>(&series_text) {
-                Ok(s) => {
-                    s
-                },
-                Err(e) => {
-                    match serde_json::from_str::<SeriesDetailDto>(&series_text) {
-                        Ok(detail) => {
-                            let special = detail.specials.first();
-                            
-                            SeriesDto {
-                                id: series_id,
-                                name: if let Some(special) = special {
-                                    special.title.clone().unwrap_or_else(|| special.range.clone())
-                                } else if !series_name.is_empty() {
-                                    series_name.clone()
-                                } else {
-                                    format!("Series {}", series_id)
-                                },
-                                coverImage: special.and_then(|s| s.coverImage.clone()),
-                            }
-                        },
-                        Err(e2) => {
-                            error!("Failed to parse series details as both SeriesDto and SeriesDetailDto");
-                            error!("SeriesDto error: {}", e);
-                            error!("SeriesDetailDto error: {}", e2);
-                            error!("Raw series response: {}", series_text);
-                            
-                            let book_url = format!(
-                                "{}/api/book/{}/book-info",
-                                config.kavita_url, progress.chapterId
-                            );
-                            
-                            let book_resp = client
-                                .get(&book_url)
-                                .header("Authorization", format!("Bearer {}", jwt_token))
-                                .send()
-                                .await?;
-                            
-                            if !book_resp.status().is_success() {
-                                error!("Failed to get book info: {}", book_resp.status());
-                                SeriesDto {
-                                    id: series_id,
-                                    name: format!("Series {}", series_id),
-                                    coverImage: None,
-                                }
-                            } else {
-                                let book_info: BookInfoDto = match book_resp.json().await {
-                                    Ok(bi) => bi,
-                                    Err(e) => {
-                                        error!("Failed to parse book info: {}", e);
-                                        return Ok(());
-                                    }
-                                };
-                                
-                                SeriesDto {
-                                    id: book_info.seriesId,
-                                    name: book_info.seriesName.clone(),
-                                    coverImage: None,
-                                }
-                            }
-                        }
-                    }
-                }
-            };
+            reading_state.speed.observe(context.library_type, context.chapter_id, context.page, SystemTime::now());
             
             reading_state.is_reading = true;
-            reading_state.current_page = progress.pageNum;
-            reading_state.total_pages = chapter.pages;
-            reading_state.last_api_time = SystemTime::now();
+            reading_state.current_page = context.page;
+            reading_state.total_pages = context.pages;
+            reading_state.last_api_time = now;
             
-            if current_book.as_ref().map_or(true, |book| {
-                book.series_id != series_id || book.chapter_id != progress.chapterId
-            }) {
+            if new_book {
                 *current_book = Some(Book {
-                    series_id: series_id,
-                    chapter_id: progress.chapterId,
+                    series_id: context.series_id,
+                    chapter_id: context.chapter_id,
                 });
             }
             
-            let author = if let Some(files) = &chapter.files {
-                if !files.is_empty() {
-                    let file_path = &files[0].filePath;
-                    file_path.split('/').nth(2).unwrap_or("Unknown Author").to_string()
-                } else {
-                    "Unknown Author".to_string()
-                }
-            } else {
-                "Unknown Author".to_string()
-            };
-            
-            let book_title = series.name.clone();
-            
-            let is_book = if chapter.chapterNumber.contains("-100000") || chapter.chapterNumber == "-100000" {
-                if let Ok(detail) = serde_json::from_str::<SeriesDetailDto>(&series_text) {
-                    let found_volume = detail.volumes.iter().any(|vol| vol.id == chapter.volumeId);
-                    
-                    if found_volume && chapter.volumeId > 0 {
-                        info!("Detected as manga volume: volumeId={}", chapter.volumeId);
-                        false
-                    } else {
-                        true
-                    }
-                } else {
-                    true
-                }
-            } else {
-                false
-            };
-            
-            let volume_info = if !is_book {
-                match serde_json::from_str::<SeriesDetailDto>(&series_text) {
-                    Ok(detail) => {
-                        get_volume_info_from_detail(&detail, chapter.volumeId, is_book)
-                    },
-                    Err(_) => {
-                        if chapter.volumeId > 0 {
-                            let volume_number = chapter.range.split('-').next()
-                                .and_then(|s| s.trim().parse::<f32>().ok())
-                                .map(|n| n.floor() as i32)
-                                .unwrap_or(0);
-                                
-                            if volume_number > 0 {
-                                info!("Using chapter range to determine volume: {}", volume_number);
-                                format!("Vol. {}", volume_number)
-                            } else {
-                                format!("Vol. {}", chapter.volumeId % 1000)
-                            }
-                        } else {
-                            "".to_string()
-                        }
-                    }
-                }
-            } else {
-                "".to_string()
-            };
-
-            let chapter_info = if is_book {
-                "".to_string()
-            } else if chapter.chapterNumber.contains("-100000") && !volume_info.is_empty() {
-                volume_info.clone()
-            } else if let Some(title) = &chapter.title {
-                if !title.is_empty() && title != &book_title {
-                    format!("Ch. {} - ", title)
-                } else {
-                    format!("Ch. {} - ", chapter.range)
-                }
-            } else {
-                format!("Ch. {} - ", chapter.range)
-            };
-            
-            let state_text = if is_book {
-                if config.show_page_numbers.unwrap_or(false) {
-                    format!("{} - Page {} of {}", author.clone(), progress.pageNum, chapter.pages)
-                } else {
-                    author.clone()
-                }
-            } else if chapter.chapterNumber.contains("-100000") && !volume_info.is_empty() {
-                if config.show_page_numbers.unwrap_or(false) {
-                    format!("{} - {} - Page {} of {}", 
-                        author.clone(),
-                        volume_info,
-                        progress.pageNum, 
-                        chapter.pages
-                    )
-                } else {
-                    format!("{} - {}", author.clone(), volume_info)
-                }
-            } else if config.show_page_numbers.unwrap_or(false) {
-                format!("{} - {} Page {} of {}", 
-                    author.clone(),
-                    chapter_info,
-                    progress.pageNum, 
-                    chapter.pages
-                )
-            } else {
-                if !chapter_info.is_empty() {
-                    format!("{} - {}", author.clone(), chapter_info)
-                } else {
-                    author.clone()
-                }
-            };
-
-            let state_text = if state_text.chars().count() > 100 { 
-                state_text.chars().take(100).collect::<String>() 
-            } else { 
-                state_text 
-            };
-            
-            let details_text = if book_title.chars().count() > 100 { 
-                book_title.chars().take(100).collect::<String>() 
-            } else { 
-                book_title.clone()
-            };
-
-            let large_text = format!("{} - {}", details_text, state_text);
-            let large_text = if large_text.chars().count() > 100 { 
-                large_text.chars().take(100).collect::<String>() 
-            } else { 
-                large_text 
+            let timing = presence::Timing {
+                now_secs: unix_secs(SystemTime::now()),
+                session_start_secs: unix_secs(reading_state.session_start),
+                pages_per_minute: reading_state.speed.pages_per_minute(context.library_type),
             };
             
-            let image_format = config.image_format.as_deref().unwrap_or("png");
-
-            let proxy_enabled = config.proxy_enabled.unwrap_or(true);
-
-            let series_cover_url = if let Some(cover_image) = &series.coverImage {
-                if !cover_image.is_empty() {
-                    let params = format!("?seriesId={}&apiKey={}&format={}", 
-                        series.id, config.kavita_api_key, image_format);
-                    Some(get_cover_url(&config.kavita_url, "/api/Image/series-cover", &params, proxy_enabled))
-                } else {
-                    None
-                }
-            } else {
-                None
-            };
-
-            let chapter_cover_url = if let Some(cover_image) = &chapter.coverImage {
-                if !cover_image.is_empty() {
-                    let params = format!("?chapterId={}&apiKey={}&format={}", 
-                        chapter.id, config.kavita_api_key, image_format);
-                    Some(get_cover_url(&config.kavita_url, "/api/Image/chapter-cover", &params, proxy_enabled))
-                } else {
-                    None
-                }
-            } else {
-                None
-            };
-
-            let actual_series_cover_url = if let Some(url) = &series_cover_url {
-                if url.starts_with("UPLOAD:") {
-                    let real_url = url.trim_start_matches("UPLOAD:");
-                    match fetch_and_upload_image(client, real_url, "https://coverart.0xgingi.xyz/upload").await {
-                        Ok(uploaded_url) => Some(uploaded_url),
-                        Err(e) => {
-                            error!("Failed to upload series cover: {}", e);
-                            None
-                        }
-                    }
-                } else {
-                    Some(url.clone())
-                }
+            let presence = if decision.action == privacy::Action::Mask {
+                presence::render_masked(config, &timing)
             } else {
-                None
+                let mut presence = presence::render(&context, config, &timing, decision.cover);
+                presence.buttons = reading_state.links.buttons(&api, config, &context).await;
+                presence
             };
-
-            let actual_chapter_cover_url = if let Some(url) = &chapter_cover_url {
-                if url.starts_with("UPLOAD:") {
-                    let real_url = url.trim_start_matches("UPLOAD:");
-                    match fetch_and_upload_image(client, real_url, "https://coverart.0xgingi.xyz/upload").await {
-                        Ok(uploaded_url) => Some(uploaded_url),
-                        Err(e) => {
-                            error!("Failed to upload chapter cover: {}", e);
-                            None
-                        }
-                    }
-                } else {
-                    Some(url.clone())
-                }
-            } else {
-                None
-            };
-
-            let mut activity_builder = activity::Activity::new()
-                .details(&details_text)
-                .state(&state_text);
-                
-            let now = SystemTime::now();
-            let now_secs = match now.duration_since(UNIX_EPOCH) {
-                Ok(d) => d.as_secs() as i64,
-                Err(_) => {
-                    error!("Failed to get current time in seconds");
-                    0
-                }
-            };
-            
-            if now_secs > 0 {
-                activity_builder = activity_builder.timestamps(
-                    activity::Timestamps::new()
-                        .start(now_secs - ((progress.pageNum as i64) * 20))
-                        .end(now_secs + 20 * (chapter.pages as i64 - progress.pageNum as i64))
-                );
-            }
-            
-            if let Some(url) = &actual_series_cover_url {
-                activity_builder = activity_builder.assets(
-                    activity::Assets::new()
-                        .large_image(url)
-                        .large_text(&large_text)
-                );
-            } else if let Some(url) = &actual_chapter_cover_url {
-                activity_builder = activity_builder.assets(
-                    activity::Assets::new()
-                        .large_image(url)
-                        .large_text(&large_text)
-                );
-            }
             
-            match discord.set_activity(activity_builder) {
+            match set_discord_activity(client, discord, &presence).await {
                 Ok(_) => {
                     info!("Updated Discord status: reading {}", 
-                        if chapter.range.contains("-100000") { 
-                            series.name.clone() 
+                        if context.is_book() || context.is_loose_leaf() { 
+                            context.series_name.clone() 
                         } else { 
-                            format!("{} ({})", series.name, chapter.range)
+                            format!("{} ({})", context.series_name, context.chapter_range)
                         }
                     );
                 },
                 Err(e) => {
                     error!("Failed to set Discord activity: {}", e);
-                    return Err(e.into());
+                    return Err(e);
                 }
             }
 
@@ -848,144 +426,113 @@ async fn update_discord_status(
         },
         Err(e) => {
             error!("Error checking current progress: {}", e);
+            if kavita::is_unauthorized(e.as_ref()) {
+                session.invalidate();
+            }
         }
     }
     
     Ok(())
 }
 
-async fn check_current_progress(
+/// Whether reading `series_id` at `now` carries on the session last active at
+/// `last_active`: the same series, read again within the inactivity timeout. Moving on to the
+/// next chapter or the status being cleared for a moment doesn't restart it.
+fn continues_session(
+    session_series: Option<i32>,
+    last_active: SystemTime,
+    series_id: i32,
+    now: SystemTime,
+    inactivity_timeout: Duration,
+) -> bool {
+    session_series == Some(series_id)
+        && now.duration_since(last_active).is_ok_and(|gap| gap <= inactivity_timeout)
+}
+
+/// The uploaded cover, or the placeholder when an obscured cover couldn't be
+/// processed or uploaded.
+fn uploaded_cover(
+    presence: &PresenceModel,
+    upload: Result<String, Box<dyn std::error::Error>>,
+) -> Option<String> {
+    match upload {
+        Ok(uploaded_url) => Some(uploaded_url),
+        Err(e) => {
+            error!("Failed to upload cover: {}", e);
+            presence.cover_effect.and(presence.cover_placeholder.clone())
+        }
+    }
+}
+
+async fn set_discord_activity(
     client: &Client,
-    config: &Config,
-    jwt_token: &str
-) -> Result<Option<(ProgressDto, i32, i32, String)>, Box<dyn std::error::Error>> {
-    let account_url = format!("{}/api/Users/myself", config.kavita_url);
+    discord: &mut DiscordIpcClient,
+    presence: &PresenceModel,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let large_image = match &presence.large_image {
+        Some(url) if url.starts_with("UPLOAD:") || presence.cover_effect.is_some() => {
+            let real_url = url.trim_start_matches("UPLOAD:");
+            let upload_url = "https://coverart.0xgingi.xyz/upload";
+            let upload = fetch_and_upload_image(client, real_url, upload_url, presence.cover_effect).await;
+            uploaded_cover(presence, upload)
+        },
+        Some(url) => Some(url.clone()),
+        None => None,
+    };
     
-    let account_response = client
-        .get(&account_url)
-        .header("Authorization", format!("Bearer {}", jwt_token))
-        .send()
-        .await?;
+    let mut activity_builder = activity::Activity::new();
     
-    let user_id = if account_response.status().is_success() {
-        let response_text = account_response.text().await?;
-        
-        match serde_json::from_str::<serde_json::Value>(&response_text) {
-            Ok(account) => {
-                account.get("id").and_then(|v| v.as_i64()).unwrap_or(1)
-            },
-            Err(e) => {
-                error!("Failed to parse account info as object: {}", e);
-                
-                match serde_json::from_str::<Vec<serde_json::Value>>(&response_text) {
-                    Ok(accounts) => {
-                        if !accounts.is_empty() {
-                            info!("Successfully parsed response as array");
-                            accounts[0].get("id").and_then(|v| v.as_i64()).unwrap_or(1)
-                        } else {
-                            error!("Account response was empty array");
-                            1
-                        }
-                    },
-                    Err(e2) => {
-                        error!("Also failed to parse as array: {}", e2);
-                        1
-                    }
-                }
-            }
-        }
-    } else {
-        error!("Failed to get account info: {}", account_response.status());
-        1
-    };
+    if !presence.details.is_empty() {
+        activity_builder = activity_builder.details(&presence.details);
+    }
     
-    let history_url = format!(
-        "{}/api/Stats/user/reading-history?userId={}", 
-        config.kavita_url, user_id
-    );
+    if !presence.state.is_empty() {
+        activity_builder = activity_builder.state(&presence.state);
+    }
     
-    let history_response = client
-        .get(&history_url)
-        .header("Authorization", format!("Bearer {}", jwt_token))
-        .send()
-        .await?;
+    if presence.start_timestamp.is_some() || presence.end_timestamp.is_some() {
+        let mut timestamps = activity::Timestamps::new();
+        if let Some(start) = presence.start_timestamp {
+            timestamps = timestamps.start(start);
+        }
+        if let Some(end) = presence.end_timestamp {
+            timestamps = timestamps.end(end);
+        }
+        activity_builder = activity_builder.timestamps(timestamps);
+    }
     
-    if history_response.status().is_success() {
-        let history_text = history_response.text().await?;
-        
-        if !history_text.contains("<!doctype html>") && !history_text.trim().is_empty() && history_text != "[]" {
-            match serde_json::from_str::<Vec<ReadHistoryEvent>>(&history_text) {
-                Ok(history_events) => {
-                    if !history_events.is_empty() {
-                        let mut events = history_events;
-                        events.sort_by(|a, b| b.readDate.cmp(&a.readDate));
-                        
-                        let most_recent = &events[0];
-                        
-                        let read_date = most_recent.readDate.clone();
-                        info!("Last reading timestamp: {}", read_date);
-
-                        let read_date_utc = most_recent.readDateUtc.clone();
-                        info!("Last reading timestamp (UTC): {}", read_date_utc);
-
-                        let event_time = match chrono::DateTime::parse_from_rfc3339(&read_date_utc) {
-                            Ok(dt) => dt.naive_utc(),
-                            Err(e) => {
-                                match chrono::NaiveDateTime::parse_from_str(
-                                    &read_date_utc.split('.').next().unwrap_or(&read_date_utc),
-                                    "%Y-%m-%dT%H:%M:%S"
-                                ) {
-                                    Ok(dt) => dt,
-                                    Err(e2) => {
-                                        error!("Error parsing UTC date '{}': {} (second attempt: {}). Using current time.", 
-                                               read_date_utc, e, e2);
-                                        chrono::Utc::now().naive_utc()
-                                    }
-                                }
-                            }
-                        };
-
-                        let now = chrono::Utc::now().naive_utc();
-                        let seconds_ago = (now - event_time).num_seconds();
-                        info!("Last activity: {} seconds ago (UTC comparison)", seconds_ago);
-
-                        let recent_threshold = (config.inactivity_timeout_minutes
-                            .unwrap_or(15) * 60) as i64;
-
-                        if seconds_ago < recent_threshold {
-                            let chapter_id = most_recent.chapterId;
-                            let series_id = most_recent.seriesId;
-                            
-                            let progress_url = format!(
-                                "{}/api/Reader/get-progress?chapterId={}",
-                                config.kavita_url, chapter_id
-                            );
-                            
-                            let progress_response = client
-                                .get(&progress_url)
-                                .header("Authorization", format!("Bearer {}", jwt_token))
-                                .send()
-                                .await?;
-                            
-                            if progress_response.status().is_success() {
-                                match progress_response.json::<ProgressDto>().await {
-                                    Ok(progress) => {
-                                        return Ok(Some((progress, series_id, 0, most_recent.seriesName.clone())));
-                                    },
-                                    Err(e) => error!("Failed to parse progress: {}", e)
-                                }
-                            }
-                        }
-                    }
-                },
-                Err(e) => error!("Failed to parse reading history: {}", e)
+    if let Some(url) = &large_image {
+        let mut assets = activity::Assets::new().large_image(url);
+        if !presence.large_text.is_empty() {
+            assets = assets.large_text(&presence.large_text);
+        }
+        if let Some(small_image) = &presence.small_image {
+            assets = assets.small_image(small_image);
+            if !presence.small_text.is_empty() {
+                assets = assets.small_text(&presence.small_text);
             }
         }
-    } else {
-        error!("Reading history API returned error: {}", history_response.status());
+        activity_builder = activity_builder.assets(assets);
+    }
+    
+    if !presence.buttons.is_empty() {
+        let buttons = presence.buttons.iter()
+            .map(|button| activity::Button::new(&button.label, &button.url))
+            .collect();
+        activity_builder = activity_builder.buttons(buttons);
     }
     
-    Ok(None)
+    discord.set_activity(activity_builder)
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(_) => {
+            error!("Failed to get time in seconds");
+            0
+        }
+    }
 }
 
 async fn check_kavita_server(client: &Client, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
@@ -1002,27 +549,6 @@ async fn check_kavita_server(client: &Client, config: &Config) -> Result<(), Box
     Ok(())
 }
 
-fn get_volume_info_from_detail(
-    detail: &SeriesDetailDto, 
-    chapter_volume_id: i32, 
-    is_book: bool
-) -> String {
-    if is_book || chapter_volume_id <= 0 {
-        return "".to_string();
-    }
-    
-    for vol in &detail.volumes {
-        if vol.id == chapter_volume_id {
-            info!("Found matching volume in detail: id={}, name={:?}, number={}", 
-                 vol.id, vol.name, vol.number);
-            
-            return format!("Vol. {}", vol.number);
-        }
-    }
-    
-    "".to_string()
-}
-
 async fn check_for_updates(client: &Client) -> Result<(), Box<dyn std::error::Error>> {
     info!("Checking for updates. Current version: {}", CURRENT_VERSION);
     
@@ -1084,9 +610,15 @@ fn get_cover_url(base_url: &str, endpoint: &str, params: &str, proxy_enabled: bo
 async fn fetch_and_upload_image(
     client: &Client,
     image_url: &str,
-    upload_url: &str
+    upload_url: &str,
+    effect: Option<CoverEffect>,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    if let Some(cached_url) = IMAGE_CACHE.lock().unwrap().get(image_url) {
+    let cache_key = match effect {
+        Some(effect) => format!("{}#{}", image_url, effect.name()),
+        None => image_url.to_string(),
+    };
+
+    if let Some(cached_url) = IMAGE_CACHE.lock().unwrap().get(&cache_key) {
         info!("Using cached image URL: {}", cached_url);
         return Ok(cached_url);
     }
@@ -1102,13 +634,19 @@ async fn fetch_and_upload_image(
         return Err(format!("Failed to fetch image: {}", image_response.status()).into());
     }
     
-    let content_type = image_response.headers()
+    let mut content_type = image_response.headers()
         .get(reqwest::header::CONTENT_TYPE)
         .and_then(|h| h.to_str().ok())
         .unwrap_or("image/jpeg")
         .to_string();
     
-    let image_bytes = image_response.bytes().await?;
+    let mut image_bytes = image_response.bytes().await?.to_vec();
+    
+    if let Some(effect) = effect {
+        info!("Applying {} to cover before upload", effect.name());
+        image_bytes = cover::apply(&image_bytes, effect)?;
+        content_type = "image/png".to_string();
+    }
     
     let upload_response = client.post(upload_url)
         .header(reqwest::header::CONTENT_TYPE, content_type)
@@ -1126,7 +664,56 @@ async fn fetch_and_upload_image(
     
     let full_url = format!("https://coverart.0xgingi.xyz{}", image_path);
     
-    IMAGE_CACHE.lock().unwrap().set(image_url.to_string(), full_url.clone());
+    IMAGE_CACHE.lock().unwrap().set(cache_key, full_url.clone());
     
     Ok(full_url)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+    #[test]
+    fn sessions_continue_across_chapters_of_the_same_series() {
+        let last_active = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let now = last_active + POLL_INTERVAL;
+        assert!(continues_session(Some(1), last_active, 1, now, TIMEOUT));
+        assert!(!continues_session(Some(1), last_active, 2, now, TIMEOUT), "another series");
+        assert!(!continues_session(None, last_active, 1, now, TIMEOUT), "nothing read yet");
+    }
+
+    #[test]
+    fn sessions_restart_after_the_inactivity_timeout() {
+        let last_active = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        assert!(continues_session(Some(1), last_active, 1, last_active + TIMEOUT, TIMEOUT));
+        let later = last_active + TIMEOUT + Duration::from_secs(1);
+        assert!(!continues_session(Some(1), last_active, 1, later, TIMEOUT));
+        let clock_went_back = last_active - Duration::from_secs(1);
+        assert!(!continues_session(Some(1), last_active, 1, clock_went_back, TIMEOUT));
+    }
+
+    #[test]
+    fn obscured_covers_that_cant_be_processed_use_the_placeholder() {
+        let presence = PresenceModel {
+            details: "Series".to_string(),
+            state: String::new(),
+            large_text: String::new(),
+            large_image: Some("cover".to_string()),
+            small_text: String::new(),
+            small_image: None,
+            cover_effect: Some(CoverEffect::Blur),
+            cover_placeholder: Some("hidden".to_string()),
+            start_timestamp: None,
+            end_timestamp: None,
+            buttons: Vec::new(),
+        };
+        let undecodable = cover::apply(b"<html>Not found</html>", CoverEffect::Blur).map(|_| String::new());
+        assert_eq!(uploaded_cover(&presence, undecodable), Some("hidden".to_string()));
+        assert_eq!(uploaded_cover(&presence, Ok("uploaded".to_string())), Some("uploaded".to_string()));
+
+        let shown = PresenceModel { cover_effect: None, ..presence };
+        assert_eq!(uploaded_cover(&shown, Err("upload failed".into())), None);
+    }
+}
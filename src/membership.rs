@@ -0,0 +1,231 @@
+//! Which Kavita collections and reading lists a series belongs to, and its
+//! scrobbling flags, so privacy can be managed from inside Kavita instead of
+//! by listing series ids by hand.
+
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::context::ReadingContext;
+use crate::kavita::KavitaApi;
+use crate::privacy::{self, Action, Decision};
+use crate::Config;
+
+/// Memberships are fetched again after this, to pick up changes made in Kavita.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The collections and reading lists containing a series, and its scrobbling flags.
+#[derive(Debug, Clone)]
+pub struct SeriesLists {
+    pub collections: Vec<String>,
+    pub reading_lists: Vec<String>,
+    /// Whether the series' library has scrobbling turned on.
+    pub library_scrobbles: bool,
+    /// Whether the user put a scrobble hold on the series.
+    pub scrobble_hold: bool,
+}
+
+impl Default for SeriesLists {
+    fn default() -> Self {
+        SeriesLists {
+            collections: Vec::new(),
+            reading_lists: Vec::new(),
+            library_scrobbles: true,
+            scrobble_hold: false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ListCache {
+    series: HashMap<i32, (SeriesLists, Instant)>,
+    checked_names: bool,
+}
+
+impl ListCache {
+    /// Memberships of `series_id`, from the cache while they are fresh. Returns
+    /// `None` when they are needed but couldn't be fetched and nothing is cached,
+    /// so the caller can keep the series private rather than guess.
+    pub async fn lookup(&mut self, api: &impl KavitaApi, config: &Config, series_id: i32) -> Option<SeriesLists> {
+        if !privacy::uses_lists(config) && !privacy::follows_scrobbling(config) {
+            return Some(SeriesLists::default());
+        }
+
+        if !self.checked_names && privacy::uses_lists(config) {
+            self.checked_names = warn_unknown_collections(api, config).await;
+        }
+
+        if let Some((lists, fetched_at)) = self.series.get(&series_id) {
+            if fetched_at.elapsed() < REFRESH_INTERVAL {
+                return Some(lists.clone());
+            }
+        }
+
+        match fetch(api, config, series_id).await {
+            Ok(lists) => {
+                info!("Series {} is in collections {:?} and reading lists {:?}, scrobbling {}, hold {}",
+                      series_id, lists.collections, lists.reading_lists,
+                      lists.library_scrobbles, lists.scrobble_hold);
+                self.series.insert(series_id, (lists.clone(), Instant::now()));
+                Some(lists)
+            },
+            Err(e) => {
+                error!("Failed to get privacy lists for series {}: {}", series_id, e);
+                self.series.get(&series_id).map(|(lists, _)| lists.clone())
+            }
+        }
+    }
+
+    /// Runs the privacy rules against `context` with its memberships. A series
+    /// whose memberships are unknown is treated as private.
+    pub async fn decide(
+        &mut self,
+        api: &impl KavitaApi,
+        config: &Config,
+        context: &ReadingContext,
+    ) -> Decision {
+        match self.lookup(api, config, context.series_id).await {
+            Some(lists) => privacy::evaluate(config, context, &lists),
+            None => {
+                info!("Privacy lists of '{}' are unknown, treating it as private", context.series_name);
+                Action::Deny.into()
+            }
+        }
+    }
+}
+
+async fn fetch(
+    api: &impl KavitaApi,
+    config: &Config,
+    series_id: i32,
+) -> Result<SeriesLists, Box<dyn std::error::Error>> {
+    let mut lists = SeriesLists::default();
+
+    if privacy::uses_lists(config) {
+        lists.collections = api.series_collections(series_id).await?.into_iter().map(|c| c.title).collect();
+        lists.reading_lists = api.series_reading_lists(series_id).await?.into_iter().map(|l| l.title).collect();
+    }
+
+    if privacy::follows_scrobbling(config) {
+        lists.library_scrobbles = api.library_allows_scrobbling(series_id).await?;
+        lists.scrobble_hold = api.has_scrobble_hold(series_id).await?;
+    }
+
+    Ok(lists)
+}
+
+/// Warns about collection names in the rules that don't exist in Kavita.
+/// Returns false if the collections couldn't be fetched, to try again later.
+async fn warn_unknown_collections(api: &impl KavitaApi, config: &Config) -> bool {
+    let collections = match api.collections().await {
+        Ok(collections) => collections,
+        Err(e) => {
+            error!("Failed to get collections: {}", e);
+            return false;
+        }
+    };
+
+    for name in privacy::collection_names(config) {
+        if !collections.iter().any(|c| c.title.eq_ignore_ascii_case(name)) {
+            warn!("Privacy rules name collection '{}', which doesn't exist in Kavita", name);
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kavita::fake::FakeKavita;
+    use crate::kavita::{CollectionDto, ReadingListDto};
+    use crate::test_config;
+    use serde_json::json;
+
+    fn lists_config() -> Config {
+        test_config(json!({ "privacy": { "rules": [{ "action": "deny", "collections": ["Private"] }] } }))
+    }
+
+    /// Series 1 is in the "Private" collection and the "Event" reading list.
+    fn fake() -> FakeKavita {
+        let mut api = FakeKavita::default();
+        api.series_collections.insert(1, vec![CollectionDto { title: "Private".to_string() }]);
+        api.series_reading_lists.insert(1, vec![ReadingListDto { id: 5, title: "Event".to_string() }]);
+        api
+    }
+
+    #[tokio::test]
+    async fn lookup_fetches_only_when_a_rule_names_a_list() {
+        let mut api = fake();
+        api.errors.insert("series_collections", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+
+        let lists = ListCache::default().lookup(&api, &test_config(json!({})), 1).await.unwrap();
+        assert!(lists.collections.is_empty() && lists.reading_lists.is_empty());
+    }
+
+    #[tokio::test]
+    async fn lookup_returns_the_titles_and_caches_them() {
+        let mut api = fake();
+        let mut cache = ListCache::default();
+
+        let lists = cache.lookup(&api, &lists_config(), 1).await.unwrap();
+        assert_eq!(lists.collections, ["Private"]);
+        assert_eq!(lists.reading_lists, ["Event"]);
+
+        api.series_collections.clear();
+        let lists = cache.lookup(&api, &lists_config(), 1).await.unwrap();
+        assert_eq!(lists.collections, ["Private"], "fresh memberships come from the cache");
+    }
+
+    #[tokio::test]
+    async fn lookup_is_none_when_memberships_fail_and_nothing_is_cached() {
+        for method in ["series_collections", "series_reading_lists"] {
+            let mut api = fake();
+            api.errors.insert(method, reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+            assert!(ListCache::default().lookup(&api, &lists_config(), 1).await.is_none(), "{}", method);
+        }
+    }
+
+    #[tokio::test]
+    async fn lookup_keeps_stale_memberships_when_the_refresh_fails() {
+        let mut api = fake();
+        let mut cache = ListCache::default();
+        cache.lookup(&api, &lists_config(), 1).await.unwrap();
+        let (_, fetched_at) = cache.series.get_mut(&1).unwrap();
+        *fetched_at -= REFRESH_INTERVAL;
+
+        api.errors.insert("series_collections", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        let lists = cache.lookup(&api, &lists_config(), 1).await.unwrap();
+        assert_eq!(lists.collections, ["Private"]);
+    }
+
+    #[tokio::test]
+    async fn lookup_reads_the_scrobbling_flags_when_followed() {
+        let config = test_config(json!({ "privacy": { "follow_scrobbling": true } }));
+        let mut api = FakeKavita { scrobble_holds: vec![1], ..fake() };
+        api.errors.insert("series_collections", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+
+        let lists = ListCache::default().lookup(&api, &config, 1).await.unwrap();
+        assert!(!lists.library_scrobbles);
+        assert!(lists.scrobble_hold);
+        assert!(lists.collections.is_empty(), "no rule names a collection");
+
+        for method in ["library_allows_scrobbling", "has_scrobble_hold"] {
+            let mut api = fake();
+            api.errors.insert(method, reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+            assert!(ListCache::default().lookup(&api, &config, 1).await.is_none(), "{}", method);
+        }
+    }
+
+    #[tokio::test]
+    async fn decide_denies_series_whose_memberships_are_unknown() {
+        let config = test_config(json!({
+            "privacy": { "rules": [{ "action": "deny", "collections": ["Other"] }] }
+        }));
+        let context = ReadingContext::sample();
+        let mut api = fake();
+        assert_eq!(ListCache::default().decide(&api, &config, &context).await.action, Action::Allow);
+
+        api.errors.insert("series_collections", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(ListCache::default().decide(&api, &config, &context).await.action, Action::Deny);
+    }
+}
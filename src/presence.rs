@@ -0,0 +1,701 @@
+use log::warn;
+use serde::Deserialize;
+
+use crate::buttons::Button;
+use crate::context::{ReadingContext, ReadingKind};
+use crate::cover::{CoverEffect, CoverMode};
+use crate::spoilers;
+use crate::template;
+use crate::Config;
+
+/// Discord rejects activity strings longer than this.
+const MAX_TEXT_LEN: usize = 128;
+
+const DEFAULT_DETAILS_TEMPLATE: &str = "{series}";
+const DEFAULT_STATE_TEMPLATE: &str = "{author}[ - {position}][ - {chapter_title}]";
+const DEFAULT_STATE_WITH_PAGES_TEMPLATE: &str =
+    "{author}[ - {position}][ - {chapter_title}][ - Page {page} of {pages}]";
+const DEFAULT_LARGE_TEXT_TEMPLATE: &str =
+    "{details} - {state}[ - Reading list: {reading_list} ({list_position}/{list_count})]";
+const DEFAULT_SMALL_TEXT_TEMPLATE: &str = "[{series_progress}][ ({series_percent}% of series)]";
+const DEFAULT_MASK_TEXT: &str = "Reading something";
+
+/// Placeholders available in every template.
+pub const PLACEHOLDERS: &[&str] = &[
+    "series", "author", "position", "volume", "chapter", "chapter_title", "section", "page", "pages", "percent",
+    "library", "genres", "pages_per_minute", "time_left", "reading_list", "list_position", "list_count",
+    "series_chapter", "series_chapters", "series_percent", "series_progress",
+];
+
+/// Extra placeholders for `large_text_template`, holding the rendered details and state.
+const LARGE_TEXT_PLACEHOLDERS: &[&str] = &["details", "state"];
+
+/// What the Discord timer shows, configured with `timestamp_mode`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampMode {
+    /// Time since the current reading session started, counting up.
+    Elapsed,
+    /// Estimated time to finish the current chapter, counting down.
+    #[default]
+    ChapterRemaining,
+    /// Kavita's estimate for finishing the whole series, counting down.
+    SeriesRemaining,
+    /// No timer.
+    None,
+}
+
+/// What to show for the current reading session, independent of where it is shown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresenceModel {
+    pub details: String,
+    pub state: String,
+    pub large_text: String,
+    pub large_image: Option<String>,
+    pub small_text: String,
+    /// Only shown next to a cover.
+    pub small_image: Option<String>,
+    /// Applied to `large_image` before it is uploaded, which it then always is.
+    pub cover_effect: Option<CoverEffect>,
+    /// Shown instead when `cover_effect` can't be applied, so the cover is never shown as is.
+    pub cover_placeholder: Option<String>,
+    pub start_timestamp: Option<i64>,
+    pub end_timestamp: Option<i64>,
+    /// Filled in by the caller, since the links may need looking up.
+    pub buttons: Vec<Button>,
+}
+
+/// Clock readings for a render, as Unix seconds, plus the learned reading speed.
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    pub now_secs: i64,
+    /// Kept across polls so the elapsed timer doesn't jump.
+    pub session_start_secs: i64,
+    /// The user's own pace in this kind of library, once enough has been observed.
+    pub pages_per_minute: Option<f64>,
+}
+
+/// The configured templates, or the defaults for those that aren't set.
+struct Templates<'a> {
+    details: &'a str,
+    state: &'a str,
+    large_text: &'a str,
+    /// `None` without a `small_image` to show it on.
+    small_text: Option<&'a str>,
+}
+
+impl<'a> Templates<'a> {
+    fn of(config: &'a Config) -> Self {
+        let state_default = if config.show_page_numbers.unwrap_or(false) {
+            DEFAULT_STATE_WITH_PAGES_TEMPLATE
+        } else {
+            DEFAULT_STATE_TEMPLATE
+        };
+        let has_small_image = config.small_image.as_ref().is_some_and(|image| !image.is_empty());
+
+        Templates {
+            details: config.details_template.as_deref().unwrap_or(DEFAULT_DETAILS_TEMPLATE),
+            state: config.state_template.as_deref().unwrap_or(state_default),
+            large_text: config.large_text_template.as_deref().unwrap_or(DEFAULT_LARGE_TEXT_TEMPLATE),
+            small_text: has_small_image
+                .then(|| config.small_text_template.as_deref().unwrap_or(DEFAULT_SMALL_TEXT_TEMPLATE)),
+        }
+    }
+}
+
+/// Whether any template that will be rendered uses one of `names`, so lookups
+/// only they need can be skipped.
+pub fn uses_placeholder(config: &Config, names: &[&str]) -> bool {
+    let templates = Templates::of(config);
+    [Some(templates.details), Some(templates.state), Some(templates.large_text), templates.small_text]
+        .into_iter()
+        .flatten()
+        .flat_map(template::placeholders)
+        .any(|name| names.contains(&name.as_str()))
+}
+
+/// Builds the presence for a reading context, with the cover shown as the privacy rules say.
+pub fn render(context: &ReadingContext, config: &Config, timing: &Timing, cover: CoverMode) -> PresenceModel {
+    let templates = Templates::of(config);
+
+    let hide_title = spoilers::hides_title(config, context);
+    let lookup = |name: &str| match name {
+        "chapter_title" | "section" if hide_title => None,
+        "position" => position(context, hide_title),
+        _ => placeholder_value(context, timing, name),
+    };
+    let details = template::truncate(&template::render(templates.details, &lookup), MAX_TEXT_LEN);
+    let state = template::truncate(&template::render(templates.state, &lookup), MAX_TEXT_LEN);
+
+    let large_text = template::render(templates.large_text, &|name: &str| match name {
+        "details" => Some(details.clone()),
+        "state" => Some(state.clone()),
+        _ => lookup(name),
+    });
+    let large_text = template::truncate(&large_text, MAX_TEXT_LEN);
+
+    let small_image = config.small_image.clone().filter(|image| !image.is_empty());
+    let small_text = match templates.small_text {
+        Some(small_text) => template::truncate(&template::render(small_text, &lookup), MAX_TEXT_LEN),
+        None => String::new(),
+    };
+
+    let (start_timestamp, end_timestamp) = if timing.now_secs > 0 {
+        timestamps(context, config.timestamp_mode.unwrap_or_default(), timing)
+    } else {
+        (None, None)
+    };
+
+    let (large_image, cover_effect) = cover_image(context, config, cover);
+    let cover_placeholder = cover_effect.and_then(|_| config.privacy.as_ref()?.cover_placeholder.clone());
+
+    PresenceModel {
+        details,
+        state,
+        large_text,
+        large_image,
+        small_text,
+        small_image,
+        cover_effect,
+        cover_placeholder,
+        start_timestamp,
+        end_timestamp,
+        buttons: Vec::new(),
+    }
+}
+
+/// A presence that gives nothing away, for series masked by the privacy rules.
+/// Only the session timer is kept, so the status doesn't visibly change.
+pub fn render_masked(config: &Config, timing: &Timing) -> PresenceModel {
+    let privacy = config.privacy.as_ref();
+
+    PresenceModel {
+        details: privacy.and_then(|p| p.mask_text.clone()).unwrap_or_else(|| DEFAULT_MASK_TEXT.to_string()),
+        state: String::new(),
+        large_text: String::new(),
+        large_image: privacy.and_then(|p| p.mask_image.clone()),
+        small_text: String::new(),
+        small_image: None,
+        cover_effect: None,
+        cover_placeholder: None,
+        start_timestamp: Some(timing.session_start_secs).filter(|start| *start > 0),
+        end_timestamp: None,
+        buttons: Vec::new(),
+    }
+}
+
+/// The image to show and the effect to apply to it. Effects need the cover to
+/// be uploaded, so with `proxy_enabled` off the placeholder is used instead.
+fn cover_image(
+    context: &ReadingContext,
+    config: &Config,
+    cover: CoverMode,
+) -> (Option<String>, Option<CoverEffect>) {
+    let url = context.series_cover_url.clone().or_else(|| context.chapter_cover_url.clone());
+    let placeholder = || config.privacy.as_ref().and_then(|p| p.cover_placeholder.clone());
+
+    let effect = match cover {
+        CoverMode::Show => return (url, None),
+        CoverMode::Placeholder => return (placeholder(), None),
+        CoverMode::Blur => CoverEffect::Blur,
+        CoverMode::Pixelate => CoverEffect::Pixelate,
+    };
+
+    if !config.proxy_enabled.unwrap_or(true) {
+        warn!("Can't {} covers with proxy_enabled off, using the placeholder", effect.name());
+        return (placeholder(), None);
+    }
+
+    match url {
+        Some(url) => (Some(url), Some(effect)),
+        None => (None, None),
+    }
+}
+
+/// Logs any placeholder in the configured templates that `render` doesn't know.
+pub fn warn_unknown_placeholders(config: &Config) {
+    let large_text_known: Vec<&str> = PLACEHOLDERS.iter().chain(LARGE_TEXT_PLACEHOLDERS).copied().collect();
+    let templates = [
+        ("details_template", &config.details_template, PLACEHOLDERS),
+        ("state_template", &config.state_template, PLACEHOLDERS),
+        ("large_text_template", &config.large_text_template, large_text_known.as_slice()),
+        ("small_text_template", &config.small_text_template, PLACEHOLDERS),
+    ];
+
+    for (setting, value, known) in templates {
+        if let Some(value) = value {
+            for name in template::unknown_placeholders(value, known) {
+                warn!("Unknown placeholder {{{}}} in {}, it will be left empty", name, setting);
+            }
+        }
+    }
+}
+
+/// Seconds left in the current chapter. Uses the learned reading speed when
+/// there is one, otherwise Kavita's estimate from the word or page count.
+fn chapter_secs_left(context: &ReadingContext, timing: &Timing) -> Option<i64> {
+    let pages_left = (context.pages - context.page).max(0);
+
+    if let Some(pages_per_minute) = timing.pages_per_minute {
+        return Some((pages_left as f64 / pages_per_minute * 60.0).round() as i64);
+    }
+
+    let fraction_left = if context.pages > 0 {
+        pages_left as f64 / context.pages as f64
+    } else {
+        1.0
+    };
+    context.chapter_hours.map(|hours| (hours * fraction_left * 3600.0).round() as i64)
+}
+
+/// Seconds left in the whole series, preferring the learned reading speed.
+fn series_secs_left(context: &ReadingContext, timing: &Timing) -> Option<i64> {
+    match timing.pages_per_minute {
+        Some(pages_per_minute) if context.series_pages > 0 => {
+            let pages_left = (context.series_pages - context.series_pages_read).max(0);
+            Some((pages_left as f64 / pages_per_minute * 60.0).round() as i64)
+        },
+        _ => context.series_hours_left.map(|hours| (hours * 3600.0).round() as i64),
+    }
+}
+
+/// Start and end of the Discord timer.
+fn timestamps(context: &ReadingContext, mode: TimestampMode, timing: &Timing) -> (Option<i64>, Option<i64>) {
+    match mode {
+        TimestampMode::Elapsed => (Some(timing.session_start_secs).filter(|start| *start > 0), None),
+        TimestampMode::ChapterRemaining => (None, chapter_secs_left(context, timing).map(|secs| timing.now_secs + secs)),
+        TimestampMode::SeriesRemaining => (None, series_secs_left(context, timing).map(|secs| timing.now_secs + secs)),
+        TimestampMode::None => (None, None),
+    }
+}
+
+/// Where the reader is, in the terms of the library type: `Vol. 3 Ch. 21`,
+/// `Issue #12`, or a book's title with how far into it they are.
+fn position(context: &ReadingContext, hide_title: bool) -> Option<String> {
+    if context.is_book() {
+        if let Some(section) = section(context).filter(|_| !hide_title) {
+            return Some(section);
+        }
+        let title = chapter_title(context).filter(|_| !hide_title);
+        return match (title, percent(context)) {
+            (Some(title), Some(percent)) => Some(format!("{} ({}%)", title, percent)),
+            (Some(title), None) => Some(title),
+            (None, Some(percent)) => Some(format!("{}%", percent)),
+            (None, None) => None,
+        };
+    }
+
+    if context.is_special {
+        return Some("Special".to_string());
+    }
+
+    let volume = context.volume_number.as_ref().map(|n| format!("Vol. {}", n));
+    let chapter = context.chapter_label().map(|label| match context.kind {
+        ReadingKind::Comic => format!("Issue #{}", label),
+        _ => format!("Ch. {}", label),
+    });
+
+    match (volume, chapter) {
+        (Some(volume), Some(chapter)) => Some(format!("{} {}", volume, chapter)),
+        (volume, chapter) => volume.or(chapter),
+    }
+}
+
+/// Where in an EPUB's table of contents the reader is, as `Part 2 · Chapter 7: The Storm`.
+fn section(context: &ReadingContext) -> Option<String> {
+    (!context.section.is_empty()).then(|| context.section.join(" · "))
+}
+
+/// The chapter title, unless it only repeats the series name.
+fn chapter_title(context: &ReadingContext) -> Option<String> {
+    context.chapter_title.clone().filter(|title| !title.is_empty() && title != &context.series_name)
+}
+
+fn percent(context: &ReadingContext) -> Option<String> {
+    percent_of(context.page, context.pages)
+}
+
+fn percent_of(part: i32, total: i32) -> Option<String> {
+    (total > 0).then(|| ((part as f64 / total as f64) * 100.0).round().to_string())
+}
+
+/// How far into the series the chapter is, e.g. `Chapter 42 of 180`. Loose
+/// volumes count as volumes.
+fn series_progress(context: &ReadingContext) -> Option<String> {
+    let chapter = context.series_chapter.filter(|_| context.series_chapters > 1)?;
+    let unit = match context.kind {
+        ReadingKind::Book => "Book",
+        ReadingKind::Comic => "Issue",
+        ReadingKind::Manga if context.is_loose_leaf() => "Volume",
+        ReadingKind::Manga => "Chapter",
+    };
+    Some(format!("{} {} of {}", unit, chapter, context.series_chapters))
+}
+
+/// Formats a duration as `1h 05m` or `12m`.
+fn format_duration(secs: i64) -> String {
+    let minutes = (secs.max(0) + 59) / 60;
+    if minutes >= 60 {
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+fn placeholder_value(context: &ReadingContext, timing: &Timing, name: &str) -> Option<String> {
+    match name {
+        "series" => Some(context.series_name.clone()),
+        "author" => context.author.clone(),
+        "volume" => context.volume_number.clone(),
+        "chapter" => context.chapter_label(),
+        // Books already show their title in {position}.
+        "chapter_title" if context.is_book() => None,
+        "chapter_title" => chapter_title(context),
+        "section" => section(context),
+        "page" => Some(context.page.to_string()),
+        "pages" => Some(context.pages.to_string()),
+        "percent" => percent(context),
+        "library" => context.library_name.clone(),
+        "genres" => context.metadata.as_ref()
+            .map(|m| m.genres.iter().map(|g| g.title.as_str()).collect::<Vec<_>>().join(", ")),
+        "pages_per_minute" => timing.pages_per_minute.map(|ppm| format!("{:.1}", ppm)),
+        "time_left" => chapter_secs_left(context, timing).map(format_duration),
+        "series_chapter" => context.series_chapter.map(|chapter| chapter.to_string()),
+        "series_chapters" => (context.series_chapters > 0).then(|| context.series_chapters.to_string()),
+        "series_percent" => percent_of(context.series_pages_read, context.series_pages),
+        "series_progress" => series_progress(context),
+        "reading_list" => context.reading_list.as_ref().map(|list| list.title.clone()),
+        "list_position" => context.reading_list.as_ref().map(|list| list.position.to_string()),
+        "list_count" => context.reading_list.as_ref().map(|list| list.count.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reading_list::ListPosition;
+    use crate::test_config;
+    use serde_json::json;
+
+    const TIMING: Timing = Timing { now_secs: 1_000_000, session_start_secs: 999_000, pages_per_minute: None };
+
+    /// The sample context, credited to "Author".
+    fn sample() -> ReadingContext {
+        ReadingContext { author: Some("Author".to_string()), ..ReadingContext::sample() }
+    }
+
+    fn render_with(context: &ReadingContext, config: serde_json::Value, timing: &Timing) -> PresenceModel {
+        render(context, &test_config(config), timing, CoverMode::Show)
+    }
+
+    #[test]
+    fn render_shows_how_far_into_a_book_the_reader_is() {
+        let context = ReadingContext {
+            chapter_number: "-100000".to_string(),
+            kind: ReadingKind::Book,
+            ..sample()
+        };
+        let presence = render_with(&context, json!({}), &TIMING);
+        assert_eq!(presence.details, "Series");
+        assert_eq!(presence.state, "Author - 25%");
+        assert_eq!(presence.large_text, "Series - Author - 25%");
+
+        let presence = render_with(&context, json!({ "show_page_numbers": true }), &TIMING);
+        assert_eq!(presence.state, "Author - 25% - Page 5 of 20");
+    }
+
+    #[test]
+    fn render_shows_the_volume_of_a_loose_leaf_volume() {
+        let context = ReadingContext {
+            chapter_number: "-100000".to_string(),
+            volume_number: Some("3".to_string()),
+            ..sample()
+        };
+        let presence = render_with(&context, json!({}), &TIMING);
+        assert_eq!(presence.state, "Author - Vol. 3");
+    }
+
+    #[test]
+    fn render_truncates_to_discords_limit() {
+        let context = ReadingContext { series_name: "ä".repeat(150), ..sample() };
+        let presence = render_with(&context, json!({}), &TIMING);
+        assert_eq!(presence.details.chars().count(), MAX_TEXT_LEN);
+    }
+
+    #[test]
+    fn render_counts_down_the_chapter_from_kavitas_estimate() {
+        let context = ReadingContext { chapter_hours: Some(0.5), ..sample() };
+        let presence = render_with(&context, json!({}), &TIMING);
+        assert_eq!(presence.start_timestamp, None);
+        assert_eq!(presence.end_timestamp, Some(TIMING.now_secs + 1350), "three quarters of half an hour");
+
+        let presence = render_with(&sample(), json!({}), &TIMING);
+        assert_eq!(presence.end_timestamp, None, "no estimate, no timer");
+    }
+
+    #[test]
+    fn render_counts_down_at_the_learned_speed() {
+        let timing = Timing { pages_per_minute: Some(1.0), ..TIMING };
+        let context = ReadingContext {
+            chapter_hours: Some(0.5),
+            series_pages: 100,
+            series_pages_read: 40,
+            ..sample()
+        };
+        let presence = render_with(&context, json!({}), &timing);
+        assert_eq!(presence.end_timestamp, Some(TIMING.now_secs + 15 * 60), "15 pages at 1 a minute");
+
+        let presence = render_with(&context, json!({ "timestamp_mode": "series_remaining" }), &timing);
+        assert_eq!(presence.end_timestamp, Some(TIMING.now_secs + 60 * 60));
+
+        let presence = render_with(&context, json!({ "state_template": "{time_left} left" }), &timing);
+        assert_eq!(presence.state, "15m left");
+    }
+
+    #[test]
+    fn render_picks_timestamps_by_mode() {
+        let context = ReadingContext {
+            chapter_hours: Some(0.5),
+            series_hours_left: Some(2.0),
+            ..sample()
+        };
+        let render_in = |mode: &str, timing: &Timing| {
+            let presence = render_with(&context, json!({ "timestamp_mode": mode }), timing);
+            (presence.start_timestamp, presence.end_timestamp)
+        };
+
+        assert_eq!(render_in("elapsed", &TIMING), (Some(TIMING.session_start_secs), None));
+        assert_eq!(render_in("series_remaining", &TIMING), (None, Some(TIMING.now_secs + 7200)));
+        assert_eq!(render_in("none", &TIMING), (None, None));
+        let no_clock = Timing { now_secs: 0, ..TIMING };
+        assert_eq!(render_in("chapter_remaining", &no_clock), (None, None));
+    }
+
+    #[test]
+    fn render_prefers_the_series_cover() {
+        let context = ReadingContext {
+            series_cover_url: Some("series".to_string()),
+            chapter_cover_url: Some("chapter".to_string()),
+            ..sample()
+        };
+        let presence = render_with(&context, json!({}), &TIMING);
+        assert_eq!(presence.large_image.as_deref(), Some("series"));
+
+        let context = ReadingContext { series_cover_url: None, ..context };
+        let presence = render_with(&context, json!({}), &TIMING);
+        assert_eq!(presence.large_image.as_deref(), Some("chapter"));
+    }
+
+    #[test]
+    fn render_uses_the_default_state_template() {
+        let context = ReadingContext {
+            volume_number: Some("3".to_string()),
+            chapter_title: Some("The Storm".to_string()),
+            ..sample()
+        };
+        let presence = render_with(&context, json!({}), &TIMING);
+        assert_eq!(presence.state, "Author - Vol. 3 Ch. 21 - The Storm");
+        assert_eq!(presence.large_text, "Series - Author - Vol. 3 Ch. 21 - The Storm");
+    }
+
+    #[test]
+    fn render_fills_in_configured_templates() {
+        let config = json!({
+            "details_template": "Reading {series}",
+            "state_template": "{percent}% of Ch. {chapter}",
+            "large_text_template": "{state} ({details})",
+        });
+        let presence = render_with(&sample(), config, &TIMING);
+        assert_eq!(presence.details, "Reading Series");
+        assert_eq!(presence.state, "25% of Ch. 21");
+        assert_eq!(presence.large_text, "25% of Ch. 21 (Reading Series)");
+    }
+
+    #[test]
+    fn render_masked_keeps_only_the_session_timer() {
+        let presence = render_masked(&test_config(json!({})), &TIMING);
+        assert_eq!(presence.details, "Reading something");
+        assert_eq!((presence.state.as_str(), presence.large_text.as_str()), ("", ""));
+        assert_eq!(presence.large_image, None);
+        assert_eq!((presence.start_timestamp, presence.end_timestamp), (Some(TIMING.session_start_secs), None));
+
+        let config = test_config(json!({ "privacy": { "mask_text": "Busy", "mask_image": "book" } }));
+        let presence = render_masked(&config, &TIMING);
+        assert_eq!(presence.details, "Busy");
+        assert_eq!(presence.large_image.as_deref(), Some("book"));
+    }
+
+    #[test]
+    fn render_leaves_out_chapter_titles_hidden_for_spoilers() {
+        let context = ReadingContext {
+            chapter_title: Some("The Storm".to_string()),
+            ..sample()
+        };
+        let presence = render_with(&context, json!({ "spoilers": { "mode": "titles" } }), &TIMING);
+        assert_eq!(presence.state, "Author - Ch. 21");
+        assert_eq!(presence.large_text, "Series - Author - Ch. 21");
+    }
+
+    #[test]
+    fn cover_image_follows_the_cover_mode() {
+        let context = ReadingContext {
+            series_cover_url: Some("cover".to_string()),
+            ..sample()
+        };
+        let config = test_config(json!({ "privacy": { "cover_placeholder": "hidden" } }));
+        let cover = Some("cover".to_string());
+
+        assert_eq!(cover_image(&context, &config, CoverMode::Show), (cover.clone(), None));
+        assert_eq!(cover_image(&context, &config, CoverMode::Blur), (cover, Some(CoverEffect::Blur)));
+        assert_eq!(cover_image(&context, &config, CoverMode::Placeholder), (Some("hidden".to_string()), None));
+        assert_eq!(cover_image(&sample(), &config, CoverMode::Pixelate), (None, None));
+    }
+
+    #[test]
+    fn cover_image_uses_the_placeholder_when_covers_cant_be_uploaded() {
+        let context = ReadingContext {
+            series_cover_url: Some("cover".to_string()),
+            ..sample()
+        };
+        let privacy = json!({ "cover_placeholder": "hidden" });
+        let config = test_config(json!({ "proxy_enabled": false, "privacy": privacy }));
+        assert_eq!(cover_image(&context, &config, CoverMode::Blur), (Some("hidden".to_string()), None));
+    }
+
+    #[test]
+    fn position_describes_where_the_reader_is() {
+        let chapter = |number: &str| ReadingContext {
+            chapter_number: number.to_string(),
+            chapter_range: number.to_string(),
+            ..sample()
+        };
+        let volume = |number: &str, context: ReadingContext| ReadingContext {
+            volume_number: Some(number.to_string()),
+            ..context
+        };
+        let book = ReadingContext {
+            kind: ReadingKind::Book,
+            chapter_number: "-100000".to_string(),
+            chapter_title: Some("The Hobbit".to_string()),
+            page: 50,
+            pages: 200,
+            ..sample()
+        };
+        let sectioned = ReadingContext { section: vec!["Part 1".to_string()], ..book.clone() };
+
+        let cases = [
+            ("chapter", chapter("21"), Some("Ch. 21")),
+            ("volume and chapter", volume("3", chapter("21")), Some("Vol. 3 Ch. 21")),
+            ("comic issue", ReadingContext { kind: ReadingKind::Comic, ..chapter("12") }, Some("Issue #12")),
+            ("decimal chapter", chapter("10.5"), Some("Ch. 10.5")),
+            ("loose-leaf volume", volume("3", chapter("-100000")), Some("Vol. 3")),
+            ("special", ReadingContext { is_special: true, ..volume("3", chapter("5")) }, Some("Special")),
+            ("book", book.clone(), Some("The Hobbit (25%)")),
+            ("book section", sectioned.clone(), Some("Part 1")),
+            ("untitled book", ReadingContext { chapter_title: None, ..book.clone() }, Some("25%")),
+            ("nothing known", chapter(""), None),
+        ];
+
+        for (case, context, expected) in cases {
+            assert_eq!(position(&context, false).as_deref(), expected, "{}", case);
+        }
+        assert_eq!(position(&book, true).as_deref(), Some("25%"), "hidden book title");
+        assert_eq!(position(&sectioned, true).as_deref(), Some("25%"), "hidden section");
+    }
+
+    #[test]
+    fn render_drops_the_author_when_nobody_is_credited() {
+        let context = ReadingContext { author: None, ..sample() };
+        let presence = render_with(&context, json!({}), &TIMING);
+        assert_eq!(presence.state, "Ch. 21");
+    }
+
+    #[test]
+    fn render_joins_the_section_path() {
+        let context = ReadingContext {
+            kind: ReadingKind::Book,
+            section: vec!["Part 2".to_string(), "Chapter 7: The Storm".to_string()],
+            ..sample()
+        };
+        let config = json!({ "state_template": "{section}" });
+        let presence = render_with(&context, config.clone(), &TIMING);
+        assert_eq!(presence.state, "Part 2 · Chapter 7: The Storm");
+
+        let config = json!({ "state_template": "{section}", "spoilers": { "mode": "titles" } });
+        let presence = render_with(&context, config, &TIMING);
+        assert_eq!(presence.state, "", "hidden with the chapter title");
+    }
+
+    #[test]
+    fn render_names_the_reading_list_in_the_large_text() {
+        let reading_list = ListPosition { title: "Event".to_string(), position: 3, count: 12 };
+        let context = ReadingContext { reading_list: Some(reading_list), ..sample() };
+        let presence = render_with(&context, json!({}), &TIMING);
+        assert_eq!(presence.large_text, "Series - Author - Ch. 21 - Reading list: Event (3/12)");
+        assert_eq!(presence.state, "Author - Ch. 21");
+    }
+
+    #[test]
+    fn render_fills_in_the_small_text_next_to_a_small_image() {
+        let context = ReadingContext {
+            series_chapter: Some(21),
+            series_chapters: 40,
+            series_pages: 800,
+            series_pages_read: 400,
+            ..sample()
+        };
+        let presence = render_with(&context, json!({}), &TIMING);
+        assert_eq!(presence.small_image, None);
+        assert_eq!(presence.small_text, "", "small text needs a small image");
+
+        let presence = render_with(&context, json!({ "small_image": "kavita" }), &TIMING);
+        assert_eq!(presence.small_image.as_deref(), Some("kavita"));
+        assert_eq!(presence.small_text, "Chapter 21 of 40 (50% of series)");
+
+        let config = json!({
+            "small_image": "kavita",
+            "small_text_template": "{series_chapter}/{series_chapters}",
+        });
+        assert_eq!(render_with(&context, config, &TIMING).small_text, "21/40");
+    }
+
+    #[test]
+    fn series_progress_names_the_unit_by_kind() {
+        let chapter = ReadingContext { series_chapter: Some(3), series_chapters: 12, ..sample() };
+        assert_eq!(series_progress(&chapter).as_deref(), Some("Chapter 3 of 12"));
+
+        let volume_file = ReadingContext { chapter_number: "-100000".to_string(), ..chapter.clone() };
+        assert_eq!(series_progress(&volume_file).as_deref(), Some("Volume 3 of 12"));
+
+        let comic = ReadingContext { kind: ReadingKind::Comic, ..volume_file.clone() };
+        assert_eq!(series_progress(&comic).as_deref(), Some("Issue 3 of 12"));
+
+        let book = ReadingContext { kind: ReadingKind::Book, ..volume_file };
+        assert_eq!(series_progress(&book).as_deref(), Some("Book 3 of 12"));
+    }
+
+    #[test]
+    fn series_progress_is_empty_for_specials_and_single_chapters() {
+        // Specials aren't in the chapter order, so they have no place in it.
+        let special = ReadingContext { is_special: true, series_chapters: 10, ..sample() };
+        assert_eq!(series_progress(&special), None);
+
+        let one_shot = ReadingContext { series_chapter: Some(1), series_chapters: 1, ..sample() };
+        assert_eq!(series_progress(&one_shot), None);
+    }
+
+    #[test]
+    fn uses_placeholder_looks_at_the_templates_that_are_shown() {
+        assert!(uses_placeholder(&test_config(json!({})), &["author"]), "default state template");
+        assert!(!uses_placeholder(&test_config(json!({})), &["time_left"]));
+
+        let config = test_config(json!({ "details_template": "{series} [{time_left} left]" }));
+        assert!(uses_placeholder(&config, &["time_left"]));
+
+        let config = test_config(json!({ "small_text_template": "{time_left}" }));
+        assert!(!uses_placeholder(&config, &["time_left"]), "small text needs a small image");
+        let config = test_config(json!({ "small_image": "kavita", "small_text_template": "{time_left}" }));
+        assert!(uses_placeholder(&config, &["time_left"]));
+    }
+}
@@ -0,0 +1,577 @@
+//! Decides whether a reading context may be shown on Discord.
+//!
+//! `privacy.rules` is an ordered list; the first rule whose predicates all
+//! match decides. When none match, `privacy.default` applies, so setting it to
+//! `deny` turns the rules into an allowlist.
+//!
+//! `privacy.max_age_rating` and `privacy.follow_scrobbling` are checked before
+//! any rule, so no rule can allow a series they hide.
+
+use log::{info, warn};
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+use crate::context::ReadingContext;
+use crate::cover::CoverMode;
+use crate::kavita::AgeRating;
+use crate::membership::SeriesLists;
+use crate::Config;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Show the presence.
+    #[default]
+    Allow,
+    /// Clear the presence.
+    Deny,
+    /// Show a generic presence without anything about what is being read.
+    Mask,
+}
+
+impl Action {
+    /// The more private of the two.
+    pub fn stricter(self, other: Action) -> Action {
+        let rank = |action: Action| match action {
+            Action::Allow => 0,
+            Action::Mask => 1,
+            Action::Deny => 2,
+        };
+        if rank(other) > rank(self) { other } else { self }
+    }
+}
+
+/// The outcome for one reading context.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub action: Action,
+    pub cover: CoverMode,
+}
+
+impl From<Action> for Decision {
+    fn from(action: Action) -> Self {
+        Decision { action, cover: CoverMode::Show }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct PrivacyConfig {
+    /// What happens when no rule matches.
+    #[serde(default)]
+    pub default: Action,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Series rated above this get `above_age_rating`, whatever the rules say.
+    #[serde(default, deserialize_with = "age_rating_name")]
+    pub max_age_rating: Option<AgeRating>,
+    #[serde(default = "default_above_age_rating")]
+    pub above_age_rating: Action,
+    /// What happens to series without a usable rating when `max_age_rating` is
+    /// set. Defaults to `above_age_rating`.
+    pub unknown_age_rating: Option<Action>,
+    /// Hide series in libraries without scrobbling and series with a scrobble hold.
+    #[serde(default)]
+    pub follow_scrobbling: bool,
+    /// Text shown instead of the series when masked, with no state or cover.
+    pub mask_text: Option<String>,
+    /// Discord asset key or image URL shown when masked.
+    pub mask_image: Option<String>,
+    /// Discord asset key or image URL shown instead of covers a rule sets to `placeholder`.
+    pub cover_placeholder: Option<String>,
+}
+
+fn default_above_age_rating() -> Action {
+    Action::Deny
+}
+
+fn age_rating_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<AgeRating>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    AgeRating::from_name(&name)
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("unknown age rating '{}'", name)))
+}
+
+/// One rule. Every predicate that is set has to match; a list predicate
+/// matches when any entry does. Text predicates are case-insensitive
+/// substring matches, except `series_name` which is a regular expression.
+#[derive(Debug, Deserialize, Default)]
+pub struct Rule {
+    /// Shown in the log when the rule decides.
+    pub name: Option<String>,
+    pub action: Action,
+    /// How the cover is shown when the rule allows the series.
+    #[serde(default)]
+    pub cover: CoverMode,
+    #[serde(default)]
+    pub library_ids: Vec<i32>,
+    #[serde(default)]
+    pub series_ids: Vec<i32>,
+    pub series_name: Option<NamePattern>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default)]
+    pub people: Vec<String>,
+    #[serde(default)]
+    pub publishers: Vec<String>,
+    #[serde(default)]
+    pub languages: Vec<String>,
+    /// `image`, `archive`, `epub` or `pdf`.
+    #[serde(default)]
+    pub formats: Vec<String>,
+    /// Kavita collection titles, matched whole but ignoring case.
+    #[serde(default)]
+    pub collections: Vec<String>,
+    /// Kavita reading list titles, matched whole but ignoring case.
+    #[serde(default)]
+    pub reading_lists: Vec<String>,
+}
+
+/// A series name regular expression, compiled when the config is loaded.
+#[derive(Debug)]
+pub struct NamePattern(Regex);
+
+impl<'de> Deserialize<'de> for NamePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern).map(NamePattern).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Rule {
+    /// Describes what matched, or `None` when any predicate doesn't.
+    fn matches(&self, context: &ReadingContext, lists: &SeriesLists) -> Option<String> {
+        let mut reasons = Vec::new();
+
+        if !self.library_ids.is_empty() {
+            if !self.library_ids.contains(&context.library_id) {
+                return None;
+            }
+            reasons.push(format!("library {}", context.library_id));
+        }
+
+        if !self.series_ids.is_empty() {
+            if !self.series_ids.contains(&context.series_id) {
+                return None;
+            }
+            reasons.push(format!("series {}", context.series_id));
+        }
+
+        if let Some(NamePattern(pattern)) = &self.series_name {
+            if !pattern.is_match(&context.series_name) {
+                return None;
+            }
+            reasons.push(format!("series name '{}'", context.series_name));
+        }
+
+        let metadata = context.metadata.as_ref();
+        let text_predicates = [
+            ("tag", &self.tags, metadata.map(|m| m.tags.iter().map(|t| t.title.as_str()).collect())),
+            ("genre", &self.genres, metadata.map(|m| m.genres.iter().map(|g| g.title.as_str()).collect())),
+            ("person", &self.people, metadata.map(|m| m.credits.people().map(|p| p.name.as_str()).collect())),
+            ("publisher", &self.publishers, metadata.map(|m| m.credits.publishers.iter().map(|p| p.name.as_str()).collect())),
+            ("language", &self.languages, metadata.and_then(|m| m.language.as_deref()).map(|l| vec![l])),
+        ];
+
+        for (kind, wanted, values) in text_predicates {
+            if wanted.is_empty() {
+                continue;
+            }
+            let values: Vec<&str> = values.unwrap_or_default();
+            let found = values.iter().find(|value| contains_any(value, wanted))?;
+            reasons.push(format!("{} '{}'", kind, found));
+        }
+
+        if !self.formats.is_empty() {
+            let format = context.format.name();
+            if !self.formats.iter().any(|f| f.eq_ignore_ascii_case(format)) {
+                return None;
+            }
+            reasons.push(format!("format {}", format));
+        }
+
+        let list_predicates = [
+            ("collection", &self.collections, &lists.collections),
+            ("reading list", &self.reading_lists, &lists.reading_lists),
+        ];
+
+        for (kind, wanted, titles) in list_predicates {
+            if wanted.is_empty() {
+                continue;
+            }
+            let found = titles.iter().find(|title| wanted.iter().any(|w| w.eq_ignore_ascii_case(title)))?;
+            reasons.push(format!("{} '{}'", kind, found));
+        }
+
+        if reasons.is_empty() {
+            reasons.push("everything".to_string());
+        }
+        Some(reasons.join(", "))
+    }
+}
+
+fn contains_any(value: &str, wanted: &[String]) -> bool {
+    let value = value.to_lowercase();
+    wanted.iter().any(|w| value.contains(&w.to_lowercase()))
+}
+
+/// Runs the rules against `context` and logs which one decided.
+pub fn evaluate(config: &Config, context: &ReadingContext, lists: &SeriesLists) -> Decision {
+    let Some(privacy) = &config.privacy else {
+        return Action::Allow.into();
+    };
+
+    if let Some(action) = check_age_rating(privacy, context) {
+        return action.into();
+    }
+
+    if privacy.follow_scrobbling {
+        if !lists.library_scrobbles {
+            info!("The library of '{}' doesn't allow scrobbling: {:?}", context.series_name, Action::Deny);
+            return Action::Deny.into();
+        }
+        if lists.scrobble_hold {
+            info!("'{}' has a scrobble hold: {:?}", context.series_name, Action::Deny);
+            return Action::Deny.into();
+        }
+    }
+
+    for (index, rule) in privacy.rules.iter().enumerate() {
+        if let Some(reason) = rule.matches(context, lists) {
+            let name = rule.name.clone().unwrap_or_else(|| format!("#{}", index + 1));
+            info!("Privacy rule {} matched {} for '{}': {:?}, cover {:?}",
+                  name, reason, context.series_name, rule.action, rule.cover);
+            return Decision { action: rule.action, cover: rule.cover };
+        }
+    }
+
+    info!("No privacy rule matched '{}': default {:?}", context.series_name, privacy.default);
+    privacy.default.into()
+}
+
+/// True when any rule needs to know the collections or reading lists of a series.
+pub fn uses_lists(config: &Config) -> bool {
+    config.privacy.as_ref().is_some_and(|privacy| {
+        privacy.rules.iter().any(|rule| !rule.collections.is_empty() || !rule.reading_lists.is_empty())
+    })
+}
+
+pub fn follows_scrobbling(config: &Config) -> bool {
+    config.privacy.as_ref().is_some_and(|privacy| privacy.follow_scrobbling)
+}
+
+/// Every collection title named by a rule.
+pub fn collection_names(config: &Config) -> impl Iterator<Item = &str> {
+    config.privacy.iter()
+        .flat_map(|privacy| &privacy.rules)
+        .flat_map(|rule| &rule.collections)
+        .map(String::as_str)
+}
+
+/// Applies `max_age_rating`. `None` when the rating is within it, so the rules decide.
+fn check_age_rating(privacy: &PrivacyConfig, context: &ReadingContext) -> Option<Action> {
+    let max = privacy.max_age_rating?;
+
+    // Without the metadata the rating could be anything, so it is treated as too high.
+    let Some(metadata) = &context.metadata else {
+        info!("The age rating of '{}' couldn't be loaded: {:?}",
+              context.series_name, privacy.above_age_rating);
+        return Some(privacy.above_age_rating);
+    };
+    let rating = metadata.ageRating;
+
+    if rating.is_unknown() {
+        let action = privacy.unknown_age_rating.unwrap_or(privacy.above_age_rating);
+        info!("'{}' has no age rating: {:?}", context.series_name, action);
+        return Some(action).filter(|action| *action != Action::Allow);
+    }
+
+    if rating > max {
+        info!("'{}' is rated {}, above the maximum of {}: {:?}",
+              context.series_name, rating.name(), max.name(), privacy.above_age_rating);
+        return Some(privacy.above_age_rating);
+    }
+
+    None
+}
+
+/// Turns the old `blacklisted_*` settings into deny rules ahead of the
+/// configured ones, keeping their original matching.
+pub fn migrate_blacklists(config: &mut Config) {
+    let mut rules = Vec::new();
+
+    if let Some(ids) = config.blacklisted_library_ids.take().filter(|v| !v.is_empty()) {
+        rules.push(Rule { library_ids: ids, ..legacy_rule("blacklisted_library_ids") });
+    }
+    if let Some(ids) = config.blacklisted_series_ids.take().filter(|v| !v.is_empty()) {
+        rules.push(Rule { series_ids: ids, ..legacy_rule("blacklisted_series_ids") });
+    }
+    for name in config.blacklisted_series_names.take().unwrap_or_default() {
+        if let Ok(pattern) = Regex::new(&regex::escape(&name)) {
+            rules.push(Rule {
+                series_name: Some(NamePattern(pattern)),
+                ..legacy_rule("blacklisted_series_names")
+            });
+        }
+    }
+    if let Some(tags) = config.blacklisted_tags.take().filter(|v| !v.is_empty()) {
+        rules.push(Rule { tags, ..legacy_rule("blacklisted_tags") });
+    }
+    if let Some(genres) = config.blacklisted_genres.take().filter(|v| !v.is_empty()) {
+        rules.push(Rule { genres, ..legacy_rule("blacklisted_genres") });
+    }
+
+    if rules.is_empty() {
+        return;
+    }
+
+    warn!("blacklisted_* settings are deprecated, use privacy rules instead (see README)");
+    let privacy = config.privacy.get_or_insert_with(PrivacyConfig::default);
+    rules.append(&mut privacy.rules);
+    privacy.rules = rules;
+}
+
+fn legacy_rule(setting: &str) -> Rule {
+    Rule {
+        name: Some(setting.to_string()),
+        action: Action::Deny,
+        ..Rule::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{self, ResolveCache};
+    use crate::kavita::fake::FakeKavita;
+    use crate::kavita::{MangaFormat, ProgressDto};
+    use crate::speed::SpeedModel;
+    use crate::test_config;
+    use serde_json::json;
+
+    /// "Series" (id 1) in library 1, an archive with tags, genres, people and a language.
+    fn context() -> ReadingContext {
+        ReadingContext {
+            metadata: Some(serde_json::from_value(json!({
+                "tags": [{ "title": "Time Travel" }],
+                "genres": [{ "title": "Science Fiction" }],
+                "writers": [{ "name": "Jane Writer" }],
+                "publishers": [{ "name": "Big Press" }],
+                "language": "ja",
+            })).unwrap()),
+            ..ReadingContext::sample()
+        }
+    }
+
+    fn action(privacy: serde_json::Value, context: &ReadingContext) -> Action {
+        let config = test_config(json!({ "privacy": privacy }));
+        evaluate(&config, context, &SeriesLists::default()).action
+    }
+
+    fn rule_matches(rule: serde_json::Value, context: &ReadingContext, lists: &SeriesLists) -> bool {
+        let mut rule = rule;
+        rule["action"] = json!("deny");
+        let config = test_config(json!({ "privacy": { "rules": [rule] } }));
+        evaluate(&config, context, lists).action == Action::Deny
+    }
+
+    #[test]
+    fn the_first_matching_rule_decides() {
+        let privacy = json!({ "rules": [
+            { "action": "mask", "series_ids": [2] },
+            { "action": "allow", "cover": "blur", "library_ids": [1] },
+            { "action": "deny", "series_ids": [1] },
+        ] });
+        let config = test_config(json!({ "privacy": privacy }));
+        let decision = evaluate(&config, &context(), &SeriesLists::default());
+        assert_eq!(decision, Decision { action: Action::Allow, cover: CoverMode::Blur });
+    }
+
+    #[test]
+    fn every_predicate_of_a_rule_has_to_match() {
+        let rule = json!({ "action": "deny", "library_ids": [1], "genres": ["romance"] });
+        assert_eq!(action(json!({ "rules": [rule] }), &context()), Action::Allow);
+    }
+
+    #[test]
+    fn default_deny_makes_the_rules_an_allowlist() {
+        let privacy = json!({ "default": "deny", "rules": [{ "action": "allow", "library_ids": [1] }] });
+        assert_eq!(action(privacy.clone(), &context()), Action::Allow);
+
+        let elsewhere = ReadingContext { library_id: 2, ..context() };
+        assert_eq!(action(privacy, &elsewhere), Action::Deny);
+    }
+
+    #[test]
+    fn rules_match_ids_and_formats() {
+        let lists = SeriesLists::default();
+        assert!(rule_matches(json!({ "library_ids": [3, 1] }), &context(), &lists));
+        assert!(!rule_matches(json!({ "library_ids": [3] }), &context(), &lists));
+        assert!(rule_matches(json!({ "series_ids": [1] }), &context(), &lists));
+        assert!(!rule_matches(json!({ "series_ids": [2] }), &context(), &lists));
+        assert!(rule_matches(json!({ "formats": ["ARCHIVE"] }), &context(), &lists));
+        let epub = ReadingContext { format: MangaFormat::Epub, ..context() };
+        assert!(!rule_matches(json!({ "formats": ["archive"] }), &epub, &lists));
+    }
+
+    #[test]
+    fn rules_match_series_names_as_regular_expressions() {
+        let lists = SeriesLists::default();
+        assert!(rule_matches(json!({ "series_name": "^Ser" }), &context(), &lists));
+        assert!(!rule_matches(json!({ "series_name": "^series$" }), &context(), &lists));
+        assert!(rule_matches(json!({ "series_name": "(?i)^series$" }), &context(), &lists));
+    }
+
+    #[test]
+    fn rules_match_metadata_as_substrings_ignoring_case() {
+        let lists = SeriesLists::default();
+        for (key, value) in [
+            ("tags", "time travel"),
+            ("genres", "FICTION"),
+            ("people", "jane"),
+            ("publishers", "big"),
+            ("languages", "JA"),
+        ] {
+            assert!(rule_matches(json!({ key: [value] }), &context(), &lists), "{} {}", key, value);
+            assert!(!rule_matches(json!({ key: ["nothing like it"] }), &context(), &lists), "{}", key);
+        }
+
+        let without_metadata = ReadingContext::sample();
+        assert!(!rule_matches(json!({ "tags": ["time travel"] }), &without_metadata, &lists));
+    }
+
+    #[test]
+    fn rules_match_whole_collection_and_reading_list_titles() {
+        let lists = SeriesLists {
+            collections: vec!["Favourites".to_string()],
+            reading_lists: vec!["Crossover Event".to_string()],
+            ..SeriesLists::default()
+        };
+        assert!(rule_matches(json!({ "collections": ["favourites"] }), &context(), &lists));
+        assert!(!rule_matches(json!({ "collections": ["favour"] }), &context(), &lists));
+        assert!(rule_matches(json!({ "reading_lists": ["crossover event"] }), &context(), &lists));
+        assert!(!rule_matches(json!({ "reading_lists": ["Crossover"] }), &context(), &lists));
+    }
+
+    #[test]
+    fn series_rated_above_the_maximum_are_denied_whatever_the_rules_say() {
+        let rated = |rating: i32| {
+            let mut context = context();
+            context.metadata.as_mut().unwrap().ageRating = AgeRating::from(rating);
+            context
+        };
+        let privacy = json!({ "max_age_rating": "Teen", "rules": [{ "action": "allow" }] });
+        assert_eq!(action(privacy.clone(), &rated(10)), Action::Deny, "Mature 17+");
+        assert_eq!(action(privacy.clone(), &rated(8)), Action::Allow, "Teen");
+        assert_eq!(action(privacy, &rated(3)), Action::Allow, "Everyone");
+
+        let privacy = json!({ "max_age_rating": "Teen", "default": "deny" });
+        assert_eq!(action(privacy, &rated(8)), Action::Deny, "within the maximum, the rules decide");
+    }
+
+    #[test]
+    fn unknown_age_ratings_follow_their_own_action() {
+        let privacy = json!({ "max_age_rating": "teen", "unknown_age_rating": "mask" });
+        for rating in [-1, 0, 1] {
+            let mut context = context();
+            context.metadata.as_mut().unwrap().ageRating = AgeRating::from(rating);
+            assert_eq!(action(privacy.clone(), &context), Action::Mask, "{}", rating);
+        }
+
+        let privacy = json!({ "max_age_rating": "teen", "unknown_age_rating": "allow", "default": "mask" });
+        assert_eq!(action(privacy, &context()), Action::Mask, "allowed unknown ratings leave it to the rules");
+    }
+
+    #[test]
+    fn unknown_age_ratings_default_to_the_above_age_rating_action() {
+        assert_eq!(action(json!({ "max_age_rating": "Teen" }), &context()), Action::Deny);
+        let privacy = json!({ "max_age_rating": "Teen", "above_age_rating": "mask" });
+        assert_eq!(action(privacy, &context()), Action::Mask);
+        assert_eq!(action(json!({}), &context()), Action::Allow, "no maximum, no check");
+    }
+
+    #[test]
+    fn series_without_metadata_are_treated_as_above_the_maximum() {
+        let unloaded = ReadingContext { metadata: None, ..context() };
+        let privacy = json!({ "max_age_rating": "Teen", "unknown_age_rating": "allow" });
+        assert_eq!(action(privacy, &unloaded), Action::Deny);
+        let privacy = json!({ "max_age_rating": "Teen", "above_age_rating": "mask" });
+        assert_eq!(action(privacy, &unloaded), Action::Mask);
+    }
+
+    #[test]
+    fn mask_is_an_action_for_rules_and_age_ratings() {
+        let privacy = json!({ "rules": [{ "action": "mask", "genres": ["fiction"] }] });
+        assert_eq!(action(privacy, &context()), Action::Mask);
+
+        let mut mature = context();
+        mature.metadata.as_mut().unwrap().ageRating = AgeRating::Mature17Plus;
+        let privacy = json!({ "max_age_rating": "Teen", "above_age_rating": "mask" });
+        assert_eq!(action(privacy.clone(), &mature), Action::Mask);
+        assert_eq!(action(privacy, &context()), Action::Mask, "unrated series too, by default");
+    }
+
+    #[tokio::test]
+    async fn series_whose_metadata_fails_to_load_can_be_masked() {
+        let mut api = FakeKavita::default();
+        let chapter = json!({ "id": 10, "range": "21", "pages": 20, "volumeId": 1 });
+        api.chapters.insert(10, serde_json::from_value(chapter).unwrap());
+        api.errors.insert("series_metadata", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        let progress = ProgressDto { chapterId: 10, pageNum: 5, libraryId: 1 };
+
+        let privacy = json!({ "max_age_rating": "Teen", "above_age_rating": "mask" });
+        let config = test_config(json!({ "privacy": privacy }));
+        let (mut cache, speed) = (ResolveCache::default(), SpeedModel::default());
+        let resolved = context::resolve(&api, &config, &mut cache, &speed, &progress, 1, "Series").await;
+        let context = resolved.unwrap();
+        assert!(context.metadata.is_none());
+        assert_eq!(evaluate(&config, &context, &SeriesLists::default()).action, Action::Mask);
+    }
+
+    #[test]
+    fn follow_scrobbling_denies_before_the_rules() {
+        let privacy = json!({ "follow_scrobbling": true, "rules": [{ "action": "allow" }] });
+        let config = test_config(json!({ "privacy": privacy }));
+        let decide = |lists: SeriesLists| evaluate(&config, &context(), &lists).action;
+
+        assert_eq!(decide(SeriesLists::default()), Action::Allow);
+        assert_eq!(decide(SeriesLists { library_scrobbles: false, ..SeriesLists::default() }), Action::Deny);
+        assert_eq!(decide(SeriesLists { scrobble_hold: true, ..SeriesLists::default() }), Action::Deny);
+
+        let unfollowed = test_config(json!({ "privacy": { "rules": [] } }));
+        let held = SeriesLists { scrobble_hold: true, ..SeriesLists::default() };
+        assert_eq!(evaluate(&unfollowed, &context(), &held).action, Action::Allow);
+    }
+
+    #[test]
+    fn max_age_rating_has_to_be_a_known_rating() {
+        let privacy = json!({ "max_age_rating": "Mature17Plus" });
+        let config: PrivacyConfig = serde_json::from_value(privacy).unwrap();
+        assert_eq!(config.max_age_rating, Some(AgeRating::Mature17Plus));
+
+        assert!(serde_json::from_value::<PrivacyConfig>(json!({ "max_age_rating": "Spicy" })).is_err());
+    }
+
+    #[test]
+    fn migrated_series_names_keep_matching_case_sensitive_substrings() {
+        let mut config = test_config(json!({
+            "blacklisted_series_names": ["Series", "a.b"],
+            "privacy": { "rules": [{ "action": "allow", "series_ids": [1] }] },
+        }));
+        migrate_blacklists(&mut config);
+        let lists = SeriesLists::default();
+
+        let decide = |series_name: &str| {
+            let context = ReadingContext { series_id: 5, series_name: series_name.to_string(), ..context() };
+            evaluate(&config, &context, &lists).action
+        };
+        assert_eq!(decide("The Series Returns"), Action::Deny);
+        assert_eq!(decide("the series returns"), Action::Allow);
+        assert_eq!(decide("a.b"), Action::Deny);
+        assert_eq!(decide("axb"), Action::Allow, "names are not regular expressions");
+
+        // The old settings come before the configured rules.
+        assert_eq!(evaluate(&config, &context(), &lists).action, Action::Deny);
+    }
+}
@@ -0,0 +1,177 @@
+//! Notices when a chapter is being read through a Kavita reading list, such as
+//! a crossover or event order spanning several series.
+//!
+//! Kavita doesn't report which list the reader was opened from, so a list
+//! counts when it holds more than one series and the item before the current
+//! chapter has been read, or the chapter is the first item.
+
+use log::{error, info};
+
+use crate::kavita::{KavitaApi, ReadingListItemDto};
+use crate::Config;
+
+/// Where the current chapter sits in a reading list.
+#[derive(Debug, Clone)]
+pub struct ListPosition {
+    pub title: String,
+    /// 1-based.
+    pub position: usize,
+    pub count: usize,
+}
+
+/// Remembers the list of the current chapter, so the lists are only fetched
+/// again when the chapter changes.
+#[derive(Debug, Default)]
+pub struct ReadingListTracker {
+    chapter_id: Option<i32>,
+    found: Option<ListPosition>,
+}
+
+impl ReadingListTracker {
+    pub async fn lookup(&mut self, api: &impl KavitaApi, config: &Config, chapter_id: i32) -> Option<ListPosition> {
+        if !config.reading_list_context.unwrap_or(true) {
+            return None;
+        }
+
+        if self.chapter_id != Some(chapter_id) {
+            match find(api, chapter_id).await {
+                Ok(found) => {
+                    match &found {
+                        Some(list) => info!("Reading chapter {} through reading list '{}' ({}/{})",
+                                            chapter_id, list.title, list.position, list.count),
+                        None => info!("Chapter {} isn't being read through a reading list", chapter_id),
+                    }
+                    self.chapter_id = Some(chapter_id);
+                    self.found = found;
+                },
+                Err(e) => {
+                    error!("Failed to get reading lists for chapter {}: {}", chapter_id, e);
+                    return None;
+                }
+            }
+        }
+
+        self.found.clone()
+    }
+}
+
+async fn find(api: &impl KavitaApi, chapter_id: i32) -> Result<Option<ListPosition>, Box<dyn std::error::Error>> {
+    for list in api.chapter_reading_lists(chapter_id).await? {
+        let mut items = api.reading_list_items(list.id).await?;
+        items.sort_by_key(|item| item.order);
+
+        if let Some(index) = followed_to(&items, chapter_id) {
+            return Ok(Some(ListPosition {
+                title: list.title,
+                position: index + 1,
+                count: items.len(),
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// The index of `chapter_id` when the list looks like it is being followed.
+fn followed_to(items: &[ReadingListItemDto], chapter_id: i32) -> Option<usize> {
+    let index = items.iter().position(|item| item.chapterId == chapter_id)?;
+
+    let first_series = items[0].seriesId;
+    if items.iter().all(|item| item.seriesId == first_series) {
+        return None;
+    }
+
+    match index.checked_sub(1).map(|previous| &items[previous]) {
+        None => Some(index),
+        Some(previous) if previous.pagesTotal > 0 && previous.pagesRead >= previous.pagesTotal => Some(index),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kavita::fake::FakeKavita;
+    use crate::kavita::ReadingListDto;
+    use crate::test_config;
+    use serde_json::json;
+
+    fn item(order: i32, chapter_id: i32, series_id: i32, pages_read: i32) -> ReadingListItemDto {
+        ReadingListItemDto {
+            order,
+            chapterId: chapter_id,
+            seriesId: series_id,
+            pagesRead: pages_read,
+            pagesTotal: 20,
+        }
+    }
+
+    /// A crossover: chapter 10 of series 1, then chapter 20 of series 2, then chapter 11 of series 1.
+    fn crossover(first_read: i32, second_read: i32) -> Vec<ReadingListItemDto> {
+        vec![item(0, 10, 1, first_read), item(1, 20, 2, second_read), item(2, 11, 1, 0)]
+    }
+
+    #[test]
+    fn followed_to_needs_the_previous_item_finished() {
+        assert_eq!(followed_to(&crossover(20, 20), 11), Some(2));
+        assert_eq!(followed_to(&crossover(20, 19), 11), None, "previous item unfinished");
+        assert_eq!(followed_to(&crossover(20, 0), 20), Some(1));
+    }
+
+    #[test]
+    fn followed_to_counts_the_first_item() {
+        assert_eq!(followed_to(&crossover(0, 0), 10), Some(0));
+    }
+
+    #[test]
+    fn followed_to_ignores_single_series_lists() {
+        let items = vec![item(0, 10, 1, 20), item(1, 11, 1, 0)];
+        assert_eq!(followed_to(&items, 10), None);
+        assert_eq!(followed_to(&items, 11), None);
+    }
+
+    #[test]
+    fn followed_to_ignores_chapters_missing_from_the_list() {
+        assert_eq!(followed_to(&crossover(20, 20), 99), None);
+    }
+
+    fn fake() -> FakeKavita {
+        let list = ReadingListDto { id: 5, title: "Event".to_string() };
+        let mut api = FakeKavita::default();
+        api.chapter_reading_lists.insert(11, vec![list]);
+        // Out of order, to check the items are sorted.
+        let mut items = crossover(20, 20);
+        items.reverse();
+        api.reading_list_items.insert(5, items);
+        api
+    }
+
+    #[tokio::test]
+    async fn lookup_finds_the_position_in_the_list() {
+        let mut tracker = ReadingListTracker::default();
+        let found = tracker.lookup(&fake(), &test_config(json!({})), 11).await.unwrap();
+        assert_eq!((found.title.as_str(), found.position, found.count), ("Event", 3, 3));
+
+        let disabled = test_config(json!({ "reading_list_context": false }));
+        assert!(tracker.lookup(&fake(), &disabled, 11).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn lookup_only_fetches_again_for_another_chapter() {
+        let mut api = fake();
+        let mut tracker = ReadingListTracker::default();
+        let config = test_config(json!({}));
+        assert!(tracker.lookup(&api, &config, 11).await.is_some());
+
+        api.reading_list_items.clear();
+        assert!(tracker.lookup(&api, &config, 11).await.is_some(), "cached");
+        assert!(tracker.lookup(&api, &config, 10).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn lookup_gives_up_when_the_list_fails_to_load() {
+        let mut api = fake();
+        api.errors.insert("reading_list_items", reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        let mut tracker = ReadingListTracker::default();
+        assert!(tracker.lookup(&api, &test_config(json!({})), 11).await.is_none());
+    }
+}
@@ -0,0 +1,191 @@
+//! Weekly time windows that restrict presence, such as quiet hours at work.
+//!
+//! Windows are checked in order and the first one that is active now and
+//! applies to what is being read decides. When none does, presence is left to
+//! the privacy rules.
+
+use chrono::{DateTime, Datelike, NaiveTime, TimeZone, Weekday};
+use chrono_tz::Tz;
+use log::info;
+use serde::{Deserialize, Deserializer};
+
+use crate::context::ReadingContext;
+use crate::privacy::Action;
+use crate::Config;
+
+/// `schedule` in the config.
+#[derive(Debug, Deserialize, Default)]
+pub struct ScheduleConfig {
+    /// IANA name such as `Europe/Berlin`. The system's local time is used without it.
+    #[serde(default, deserialize_with = "timezone_name")]
+    pub timezone: Option<Tz>,
+    #[serde(default)]
+    pub windows: Vec<Window>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Window {
+    /// Shown in the log while the window is active.
+    pub name: Option<String>,
+    #[serde(default = "default_window_action")]
+    pub action: Action,
+    /// `mon`..`sun`, `weekdays` or `weekends`. Every day when empty.
+    #[serde(default, deserialize_with = "day_names")]
+    pub days: Vec<Weekday>,
+    /// `HH:MM`. A window where `to` is before `from` runs past midnight.
+    /// Without times, the window lasts all day.
+    #[serde(default, deserialize_with = "time_of_day")]
+    pub from: Option<NaiveTime>,
+    #[serde(default, deserialize_with = "time_of_day")]
+    pub to: Option<NaiveTime>,
+    /// Limits the window to these libraries. All libraries when empty.
+    #[serde(default)]
+    pub library_ids: Vec<i32>,
+    /// Limits the window to these series. All series when empty.
+    #[serde(default)]
+    pub series_ids: Vec<i32>,
+}
+
+fn default_window_action() -> Action {
+    Action::Deny
+}
+
+fn timezone_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Tz>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    name.parse::<Tz>().map(Some).map_err(serde::de::Error::custom)
+}
+
+fn day_names<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Weekday>, D::Error> {
+    let mut days = Vec::new();
+    for name in Vec::<String>::deserialize(deserializer)? {
+        match name.to_lowercase().as_str() {
+            "weekdays" => days.extend([Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri]),
+            "weekends" | "weekend" => days.extend([Weekday::Sat, Weekday::Sun]),
+            other => days.push(other.parse::<Weekday>()
+                .map_err(|_| serde::de::Error::custom(format!("unknown day '{}'", name)))?),
+        }
+    }
+    Ok(days)
+}
+
+fn time_of_day<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<NaiveTime>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&text, "%H:%M")
+        .map(Some)
+        .map_err(|_| serde::de::Error::custom(format!("invalid time '{}', expected HH:MM", text)))
+}
+
+impl Window {
+    fn applies_to(&self, context: &ReadingContext) -> bool {
+        (self.library_ids.is_empty() || self.library_ids.contains(&context.library_id))
+            && (self.series_ids.is_empty() || self.series_ids.contains(&context.series_id))
+    }
+
+    fn on_day(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn is_active<T: TimeZone>(&self, now: &DateTime<T>) -> bool {
+        let today = now.weekday();
+        let time = now.time();
+        let from = self.from.unwrap_or(NaiveTime::MIN);
+
+        match self.to {
+            Some(to) if to <= from => {
+                (self.on_day(today) && time >= from) || (self.on_day(today.pred()) && time < to)
+            },
+            Some(to) => self.on_day(today) && time >= from && time < to,
+            None => self.on_day(today) && time >= from,
+        }
+    }
+
+    fn describe(&self, index: usize) -> String {
+        let name = self.name.clone().unwrap_or_else(|| format!("#{}", index + 1));
+        let time = |t: Option<NaiveTime>| t.map_or("-".to_string(), |t| t.format("%H:%M").to_string());
+        format!("{} ({:?} {}-{})", name, self.days, time(self.from), time(self.to))
+    }
+}
+
+/// The action of the first window that is active now for `context`, if any.
+pub fn evaluate(config: &Config, context: &ReadingContext) -> Option<Action> {
+    let schedule = config.schedule.as_ref().filter(|s| !s.windows.is_empty())?;
+
+    let active = match schedule.timezone {
+        Some(tz) => first_active(schedule, context, &chrono::Utc::now().with_timezone(&tz)),
+        None => first_active(schedule, context, &chrono::Local::now()),
+    };
+
+    match active {
+        Some((index, window)) => {
+            info!("Schedule window {} is active: {:?}", window.describe(index), window.action);
+            Some(window.action)
+        },
+        None => {
+            info!("No schedule window is active");
+            None
+        }
+    }
+}
+
+fn first_active<'a, T: TimeZone>(
+    schedule: &'a ScheduleConfig,
+    context: &ReadingContext,
+    now: &DateTime<T>,
+) -> Option<(usize, &'a Window)> {
+    schedule.windows.iter()
+        .enumerate()
+        .find(|(_, window)| window.applies_to(context) && window.is_active(now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Europe::Berlin;
+    use serde_json::json;
+
+    fn window(value: serde_json::Value) -> Window {
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// A time in Berlin in the first week of March 2024, which starts on a Friday.
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Tz> {
+        Berlin.with_ymd_and_hms(2024, 3, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn weekday_windows_cover_their_hours_on_weekdays() {
+        let work = window(json!({ "days": ["weekdays"], "from": "09:00", "to": "17:00" }));
+        assert!(work.is_active(&at(1, 9, 0)), "Friday at the start");
+        assert!(work.is_active(&at(4, 16, 59)), "Monday afternoon");
+        assert!(!work.is_active(&at(1, 17, 0)), "the end is exclusive");
+        assert!(!work.is_active(&at(1, 8, 59)));
+        assert!(!work.is_active(&at(2, 12, 0)), "Saturday");
+    }
+
+    #[test]
+    fn overnight_windows_run_into_the_next_day() {
+        let friday_night = window(json!({ "days": ["fri"], "from": "22:00", "to": "06:00" }));
+        assert!(friday_night.is_active(&at(1, 22, 0)), "Friday evening");
+        assert!(friday_night.is_active(&at(2, 5, 59)), "Saturday morning");
+        assert!(!friday_night.is_active(&at(2, 6, 0)));
+        assert!(!friday_night.is_active(&at(2, 23, 0)), "Saturday evening");
+        assert!(!friday_night.is_active(&at(1, 5, 0)), "Friday morning belongs to Thursday night");
+    }
+
+    #[test]
+    fn equal_times_make_a_window_last_a_whole_day() {
+        let day = window(json!({ "days": ["sat"], "from": "12:00", "to": "12:00" }));
+        assert!(day.is_active(&at(2, 12, 0)));
+        assert!(day.is_active(&at(3, 11, 59)), "until noon on Sunday");
+        assert!(!day.is_active(&at(3, 12, 0)));
+        assert!(!day.is_active(&at(2, 11, 59)));
+    }
+
+    #[test]
+    fn windows_without_times_last_all_of_their_days() {
+        let weekend = window(json!({ "days": ["weekends"] }));
+        assert!(weekend.is_active(&at(2, 0, 0)));
+        assert!(weekend.is_active(&at(3, 23, 59)));
+        assert!(!weekend.is_active(&at(1, 23, 59)));
+    }
+}
@@ -0,0 +1,295 @@
+use base64::Engine;
+use log::{error, info, warn};
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::Config;
+
+/// Renew the JWT when it has less than this much lifetime left.
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Used when the token's `exp` claim can't be read.
+const FALLBACK_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// Reported to Kavita when authenticating through the plugin endpoint.
+const PLUGIN_NAME: &str = "kavita-discord-rpc";
+
+/// How the session obtains a fresh JWT.
+#[derive(Debug)]
+enum AuthMode<'a> {
+    /// `/api/Account/login` with the configured username and password.
+    Password { username: &'a str, password: &'a str },
+    /// `/api/Plugin/authenticate` with the API key alone.
+    ApiKey,
+}
+
+impl<'a> AuthMode<'a> {
+    /// Password login is used when both credentials are configured, otherwise
+    /// the API key is exchanged for a token.
+    fn from_config(config: &'a Config) -> Self {
+        match (config.kavita_username.as_deref(), config.kavita_password.as_deref()) {
+            (Some(username), Some(password)) if !username.is_empty() && !password.is_empty() => {
+                AuthMode::Password { username, password }
+            },
+            _ => AuthMode::ApiKey,
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct UserDto {
+    username: Option<String>,
+    token: Option<String>,
+    refreshToken: Option<String>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct TokenRequestDto {
+    token: Option<String>,
+    refreshToken: Option<String>,
+}
+
+/// A Kavita login that is kept across polls. The JWT is reused until it is
+/// close to expiry, renewed through the refresh token, and only replaced by a
+/// full login when the refresh is rejected.
+#[derive(Debug, Default)]
+pub struct Session {
+    token: Option<String>,
+    refresh_token: Option<String>,
+    expires_at: Option<SystemTime>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session::default()
+    }
+
+    /// Returns a usable JWT, refreshing or logging in again as needed.
+    pub async fn token(
+        &mut self,
+        client: &Client,
+        config: &Config,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if let Some(token) = &self.token {
+            if !self.needs_refresh() {
+                return Ok(token.clone());
+            }
+        }
+
+        if self.token.is_some() && self.refresh_token.is_some() {
+            match self.refresh(client, config).await {
+                Ok(()) => {
+                    if let Some(token) = &self.token {
+                        return Ok(token.clone());
+                    }
+                },
+                Err(e) => {
+                    warn!("Failed to refresh Kavita token, logging in again: {}", e);
+                }
+            }
+        }
+
+        self.login(client, config).await?;
+        self.token.clone().ok_or_else(|| "JWT token not found in login response".into())
+    }
+
+    /// Drops the current JWT so the next call to [`Session::token`] renews it.
+    /// The refresh token is kept so renewal can skip the full login.
+    pub fn invalidate(&mut self) {
+        self.expires_at = Some(UNIX_EPOCH);
+    }
+
+    fn needs_refresh(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => match expires_at.duration_since(SystemTime::now()) {
+                Ok(remaining) => remaining < REFRESH_MARGIN,
+                Err(_) => true,
+            },
+            None => true,
+        }
+    }
+
+    async fn login(&mut self, client: &Client, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        let login_response = match AuthMode::from_config(config) {
+            AuthMode::Password { username, password } => {
+                let login_url = format!("{}/api/Account/login", config.kavita_url);
+                info!("Logging in to Kavita at: {}", login_url);
+
+                let login_data = serde_json::json!({
+                    "username": username,
+                    "password": password
+                });
+
+                client
+                    .post(&login_url)
+                    .json(&login_data)
+                    .send()
+                    .await?
+            },
+            AuthMode::ApiKey => {
+                let auth_url = format!("{}/api/Plugin/authenticate", config.kavita_url);
+                info!("Authenticating with Kavita API key at: {}", auth_url);
+
+                client
+                    .post(&auth_url)
+                    .query(&[("apiKey", config.kavita_api_key.as_str()), ("pluginName", PLUGIN_NAME)])
+                    .send()
+                    .await?
+            }
+        };
+
+        if !login_response.status().is_success() {
+            let status = login_response.status();
+            let error_text = login_response.text().await?;
+            error!("Login error: {}", error_text);
+            return Err(format!("Login failed: {}", status).into());
+        }
+
+        let user_data: UserDto = login_response.json().await?;
+        let token = user_data.token.ok_or("JWT token not found in login response")?;
+        info!("Successfully logged in as {}", user_data.username.unwrap_or_default());
+
+        self.store(token, user_data.refreshToken);
+        Ok(())
+    }
+
+    async fn refresh(&mut self, client: &Client, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        let refresh_url = format!("{}/api/Account/refresh-token", config.kavita_url);
+        info!("Refreshing Kavita token at: {}", refresh_url);
+
+        let refresh_data = serde_json::json!({
+            "token": self.token,
+            "refreshToken": self.refresh_token
+        });
+
+        let refresh_response = client
+            .post(&refresh_url)
+            .json(&refresh_data)
+            .send()
+            .await?;
+
+        if !refresh_response.status().is_success() {
+            self.refresh_token = None;
+            return Err(format!("Token refresh failed: {}", refresh_response.status()).into());
+        }
+
+        let tokens: TokenRequestDto = refresh_response.json().await?;
+        let token = tokens.token.ok_or("JWT token not found in refresh response")?;
+        info!("Refreshed Kavita token");
+
+        self.store(token, tokens.refreshToken);
+        Ok(())
+    }
+
+    fn store(&mut self, token: String, refresh_token: Option<String>) {
+        self.expires_at = Some(token_expiry(&token).unwrap_or_else(|| {
+            warn!("Could not read expiry from Kavita token, assuming {} minutes",
+                  FALLBACK_TOKEN_LIFETIME.as_secs() / 60);
+            SystemTime::now() + FALLBACK_TOKEN_LIFETIME
+        }));
+        self.token = Some(token);
+        if refresh_token.is_some() {
+            self.refresh_token = refresh_token;
+        }
+    }
+}
+
+/// Reads the `exp` claim from a JWT without verifying its signature.
+fn token_expiry(token: &str) -> Option<SystemTime> {
+    let payload = token.split('.').nth(1)?;
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&decoded).ok()?;
+    let exp = claims.get("exp")?.as_u64()?;
+    Some(UNIX_EPOCH + Duration::from_secs(exp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// An unsigned JWT carrying `claims`.
+    fn jwt(claims: &str) -> String {
+        let encode = |part: &str| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(part);
+        format!("{}.{}.signature", encode(r#"{"alg":"HS256","typ":"JWT"}"#), encode(claims))
+    }
+
+    fn expiring_in(secs: u64) -> Session {
+        let mut session = Session::new();
+        let exp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + secs;
+        session.store(jwt(&format!(r#"{{"exp":{}}}"#, exp)), Some("refresh".to_string()));
+        session
+    }
+
+    #[test]
+    fn token_expiry_reads_the_exp_claim() {
+        let expiry = token_expiry(&jwt(r#"{"nameid":"reader","exp":1700000000}"#));
+        assert_eq!(expiry, Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)));
+    }
+
+    #[test]
+    fn token_expiry_is_none_without_a_usable_exp() {
+        assert_eq!(token_expiry(&jwt(r#"{"nameid":"reader"}"#)), None, "no exp");
+        assert_eq!(token_expiry(&jwt(r#"{"exp":"soon"}"#)), None, "exp isn't a number");
+        assert_eq!(token_expiry(&jwt("not json")), None);
+        assert_eq!(token_expiry("header.!!!.signature"), None, "payload isn't base64");
+        assert_eq!(token_expiry("no-dots"), None);
+    }
+
+    #[test]
+    fn tokens_are_refreshed_within_five_minutes_of_expiry() {
+        assert!(!expiring_in(6 * 60).needs_refresh());
+        assert!(expiring_in(4 * 60).needs_refresh());
+        assert!(expiring_in(0).needs_refresh());
+    }
+
+    #[test]
+    fn unreadable_tokens_are_assumed_to_last_an_hour() {
+        let mut session = Session::new();
+        session.store("opaque".to_string(), None);
+        let remaining = session.expires_at.unwrap().duration_since(SystemTime::now()).unwrap();
+        assert!(remaining > FALLBACK_TOKEN_LIFETIME - Duration::from_secs(60));
+        assert!(!session.needs_refresh());
+    }
+
+    #[test]
+    fn invalidate_forces_a_refresh_but_keeps_the_refresh_token() {
+        let mut session = expiring_in(60 * 60);
+        session.invalidate();
+        assert!(session.needs_refresh());
+        assert_eq!(session.refresh_token.as_deref(), Some("refresh"));
+    }
+
+    #[test]
+    fn store_keeps_the_refresh_token_when_none_is_returned() {
+        let mut session = expiring_in(60 * 60);
+        session.store(jwt(r#"{"exp":1}"#), None);
+        assert_eq!(session.refresh_token.as_deref(), Some("refresh"));
+        assert!(Session::new().needs_refresh(), "no token yet");
+    }
+
+    #[test]
+    fn password_login_needs_both_credentials() {
+        let config = crate::test_config(json!({ "kavita_username": "reader", "kavita_password": "secret" }));
+        assert!(matches!(
+            AuthMode::from_config(&config),
+            AuthMode::Password { username: "reader", password: "secret" }
+        ));
+
+        for credentials in [
+            json!({}),
+            json!({ "kavita_username": "reader" }),
+            json!({ "kavita_password": "secret" }),
+            json!({ "kavita_username": "", "kavita_password": "secret" }),
+            json!({ "kavita_username": "reader", "kavita_password": "" }),
+        ] {
+            let config = crate::test_config(credentials.clone());
+            assert!(matches!(AuthMode::from_config(&config), AuthMode::ApiKey), "{}", credentials);
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Listens to Kavita's SignalR message hub so presence can refresh as soon as
+//! progress is saved, instead of waiting for the next poll.
+//!
+//! Only the small part of the SignalR JSON protocol that Kavita needs is
+//! implemented: a websocket connection without negotiation, the handshake,
+//! invocation messages and pings. Records are terminated by `0x1e`.
+
+use futures::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_tungstenite::tungstenite::Message;
+
+const RECORD_SEPARATOR: char = '\u{1e}';
+
+/// SignalR's default keep-alive; the server drops clients that stay silent for twice this.
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Wait this long before trying to reconnect after the hub went away.
+const RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Progress events often come in bursts while paging; they are merged over this window.
+const EVENT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Something Kavita pushed over the hub.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HubEvent {
+    /// Reading progress was saved for the logged-in user.
+    ProgressUpdate { series_id: i32, chapter_id: i32, pages_read: i32 },
+    /// The list of users currently online changed.
+    OnlineUsers,
+}
+
+#[derive(Debug, Deserialize)]
+struct HubMessage {
+    #[serde(rename = "type")]
+    kind: i32,
+    target: Option<String>,
+    #[serde(default)]
+    arguments: Vec<serde_json::Value>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct ProgressBody {
+    #[serde(default)]
+    seriesId: i32,
+    #[serde(default)]
+    chapterId: i32,
+    #[serde(default)]
+    pagesRead: i32,
+}
+
+/// A background connection to `/hubs/messages` that is re-established when it drops.
+pub struct EventHub {
+    sender: mpsc::UnboundedSender<HubEvent>,
+    events: mpsc::UnboundedReceiver<HubEvent>,
+    connected: Arc<AtomicBool>,
+    task: Option<JoinHandle<()>>,
+    last_attempt: Option<Instant>,
+}
+
+impl EventHub {
+    pub fn new() -> Self {
+        let (sender, events) = mpsc::unbounded_channel();
+        EventHub {
+            sender,
+            events,
+            connected: Arc::new(AtomicBool::new(false)),
+            task: None,
+            last_attempt: None,
+        }
+    }
+
+    /// True once the handshake has completed and until the socket closes.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// True when there is no live connection and the reconnect delay has passed.
+    pub fn should_connect(&self) -> bool {
+        let running = self.task.as_ref().is_some_and(|task| !task.is_finished());
+        !running && self.last_attempt.is_none_or(|at| at.elapsed() >= RECONNECT_DELAY)
+    }
+
+    /// Starts connecting in the background with the given JWT.
+    pub fn connect(&mut self, kavita_url: &str, token: &str) {
+        self.last_attempt = Some(Instant::now());
+
+        let url = match hub_url(kavita_url, token) {
+            Ok(url) => url,
+            Err(e) => {
+                error!("Invalid Kavita URL for the SignalR hub: {}", e);
+                return;
+            }
+        };
+
+        let sender = self.sender.clone();
+        let connected = self.connected.clone();
+        self.task = Some(tokio::spawn(async move {
+            if let Err(e) = run(&url, &sender, &connected).await {
+                warn!("Kavita SignalR hub unavailable, falling back to polling: {}", e);
+            } else {
+                info!("Kavita SignalR hub closed the connection, falling back to polling");
+            }
+            connected.store(false, Ordering::Relaxed);
+        }));
+    }
+
+    /// Waits for the next progress update. Bursts are already merged by the
+    /// connection task, so this is safe to cancel in a `select!`.
+    pub async fn next_progress(&mut self) -> HubEvent {
+        loop {
+            match self.events.recv().await {
+                Some(event @ HubEvent::ProgressUpdate { .. }) => return event,
+                Some(HubEvent::OnlineUsers) => {
+                    info!("Kavita reported a change in online users");
+                },
+                // The hub keeps its own sender, so the channel never closes.
+                None => std::future::pending::<()>().await,
+            }
+        }
+    }
+}
+
+/// Holds progress updates back for [`EVENT_DEBOUNCE`] after the first one,
+/// then passes on only the latest. Other events go out straight away.
+struct Debounce<'a> {
+    sender: &'a mpsc::UnboundedSender<HubEvent>,
+    pending: Option<HubEvent>,
+    flush_at: time::Instant,
+}
+
+impl<'a> Debounce<'a> {
+    fn new(sender: &'a mpsc::UnboundedSender<HubEvent>) -> Self {
+        Debounce { sender, pending: None, flush_at: time::Instant::now() }
+    }
+
+    fn push(&mut self, event: HubEvent) {
+        if !matches!(event, HubEvent::ProgressUpdate { .. }) {
+            let _ = self.sender.send(event);
+            return;
+        }
+        if self.pending.is_none() {
+            self.flush_at = time::Instant::now() + EVENT_DEBOUNCE;
+        }
+        self.pending = Some(event);
+    }
+
+    fn flush(&mut self) {
+        if let Some(event) = self.pending.take() {
+            let _ = self.sender.send(event);
+        }
+    }
+
+    /// Passes on the events in a text frame, which may hold several records.
+    fn push_records(&mut self, text: &str) -> Result<(), HubClosed> {
+        for record in text.split(RECORD_SEPARATOR).filter(|r| !r.is_empty()) {
+            if let Some(event) = parse_record(record)? {
+                self.push(event);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn hub_url(kavita_url: &str, token: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut url = url::Url::parse(kavita_url)?;
+    let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+    url.set_scheme(scheme).map_err(|_| "unsupported URL scheme")?;
+
+    let path = format!("{}/hubs/messages", url.path().trim_end_matches('/'));
+    url.set_path(&path);
+    url.query_pairs_mut().append_pair("access_token", token);
+    Ok(url.to_string())
+}
+
+async fn run(
+    url: &str,
+    sender: &mpsc::UnboundedSender<HubEvent>,
+    connected: &AtomicBool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (socket, _) = tokio_tungstenite::connect_async(url).await?;
+    let (mut write, mut read) = socket.split();
+
+    write.send(Message::text(format!("{{\"protocol\":\"json\",\"version\":1}}{}", RECORD_SEPARATOR))).await?;
+
+    let reply = match read.next().await {
+        Some(Ok(Message::Text(text))) => text,
+        Some(Ok(other)) => return Err(format!("unexpected handshake reply: {:?}", other).into()),
+        Some(Err(e)) => return Err(e.into()),
+        None => return Err("connection closed during handshake".into()),
+    };
+    let rest = check_handshake(reply.as_str())?;
+
+    connected.store(true, Ordering::Relaxed);
+    info!("Connected to Kavita SignalR hub, presence will update on progress events");
+
+    let mut debounce = Debounce::new(sender);
+    debounce.push_records(rest).map_err(|HubClosed(reason)| reason)?;
+
+    let mut ping = time::interval(PING_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ping.tick() => {
+                write.send(Message::text(format!("{{\"type\":6}}{}", RECORD_SEPARATOR))).await?;
+            },
+            _ = time::sleep_until(debounce.flush_at), if debounce.pending.is_some() => debounce.flush(),
+            message = read.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    debounce.push_records(text.as_str()).map_err(|HubClosed(reason)| reason)?;
+                },
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {},
+                Some(Err(e)) => return Err(e.into()),
+            },
+        }
+    }
+}
+
+/// Checks the handshake response, the first record of the first frame. The
+/// server may send the first messages in the same frame, so the rest of it is
+/// returned.
+fn check_handshake(frame: &str) -> Result<&str, Box<dyn std::error::Error>> {
+    let (handshake, rest) = frame.split_once(RECORD_SEPARATOR).unwrap_or((frame, ""));
+    let response: serde_json::Value = serde_json::from_str(handshake)?;
+    if let Some(error) = response.get("error").and_then(|e| e.as_str()) {
+        return Err(format!("handshake rejected: {}", error).into());
+    }
+    Ok(rest)
+}
+
+struct HubClosed(String);
+
+/// Turns one SignalR record into an event. Pings and unrelated invocations give `None`.
+fn parse_record(record: &str) -> Result<Option<HubEvent>, HubClosed> {
+    let message: HubMessage = match serde_json::from_str(record) {
+        Ok(message) => message,
+        Err(e) => {
+            warn!("Ignoring unreadable SignalR message: {}", e);
+            return Ok(None);
+        }
+    };
+
+    match message.kind {
+        // Invocation
+        1 => {},
+        // Close
+        7 => return Err(HubClosed("server closed the hub connection".to_string())),
+        _ => return Ok(None),
+    }
+
+    // Kavita wraps every event as { name, body, ... } in the first argument.
+    let body = message.arguments.first().and_then(|arg| arg.get("body"));
+    let event = match message.target.as_deref() {
+        Some("UserProgressUpdate") => body
+            .and_then(|body| serde_json::from_value::<ProgressBody>(body.clone()).ok())
+            .map(|body| HubEvent::ProgressUpdate {
+                series_id: body.seriesId,
+                chapter_id: body.chapterId,
+                pages_read: body.pagesRead,
+            }),
+        Some("OnlineUsers") => Some(HubEvent::OnlineUsers),
+        _ => None,
+    };
+
+    Ok(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRESS: &str = concat!(
+        r#"{"type":1,"target":"UserProgressUpdate","arguments":"#,
+        r#"[{"name":"UserProgressUpdate","body":{"seriesId":3,"chapterId":42,"pagesRead":7}}]}"#,
+    );
+
+    #[test]
+    fn parse_record_reads_progress_invocations() {
+        let event = HubEvent::ProgressUpdate { series_id: 3, chapter_id: 42, pages_read: 7 };
+        assert_eq!(parse_record(PROGRESS).ok(), Some(Some(event)));
+    }
+
+    #[test]
+    fn parse_record_reads_online_users() {
+        let record = r#"{"type":1,"target":"OnlineUsers","arguments":[{"name":"OnlineUsers","body":[]}]}"#;
+        assert_eq!(parse_record(record).ok(), Some(Some(HubEvent::OnlineUsers)));
+    }
+
+    #[test]
+    fn parse_record_ignores_other_invocations_and_pings() {
+        let record = r#"{"type":1,"target":"NotificationProgress","arguments":[{"body":{}}]}"#;
+        assert_eq!(parse_record(record).ok(), Some(None));
+        assert_eq!(parse_record(r#"{"type":6}"#).ok(), Some(None));
+        assert_eq!(parse_record("not json").ok(), Some(None));
+    }
+
+    #[test]
+    fn parse_record_stops_at_close() {
+        assert!(parse_record(r#"{"type":7,"error":"Server is shutting down"}"#).is_err());
+    }
+
+    #[test]
+    fn debounce_keeps_only_the_latest_progress_update() {
+        let (sender, mut events) = mpsc::unbounded_channel();
+        let mut debounce = Debounce::new(&sender);
+        let later = PROGRESS.replace(r#""pagesRead":7"#, r#""pagesRead":8"#);
+        let online = r#"{"type":1,"target":"OnlineUsers","arguments":[]}"#;
+
+        let frame = format!("{PROGRESS}{sep}{online}{sep}{later}{sep}", sep = RECORD_SEPARATOR);
+        assert!(debounce.push_records(&frame).is_ok());
+        assert_eq!(events.try_recv().ok(), Some(HubEvent::OnlineUsers));
+        assert!(events.try_recv().is_err(), "progress waits for the debounce");
+
+        debounce.flush();
+        let event = HubEvent::ProgressUpdate { series_id: 3, chapter_id: 42, pages_read: 8 };
+        assert_eq!(events.try_recv().ok(), Some(event));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn check_handshake_returns_records_sharing_its_frame() {
+        let frame = format!("{{}}{sep}{}{sep}", PROGRESS, sep = RECORD_SEPARATOR);
+        let rest = check_handshake(&frame).unwrap();
+        assert_eq!(rest, format!("{}{}", PROGRESS, RECORD_SEPARATOR));
+
+        assert_eq!(check_handshake("{}\u{1e}").unwrap(), "");
+        assert!(check_handshake("{\"error\":\"Requested protocol 'json' is not available.\"}\u{1e}").is_err());
+    }
+
+    #[test]
+    fn hub_url_switches_to_websockets_under_the_base_path() {
+        let url = hub_url("https://example.com/kavita/", "a b").unwrap();
+        assert_eq!(url, "wss://example.com/kavita/hubs/messages?access_token=a+b");
+        assert_eq!(hub_url("http://nas:5000", "t").unwrap(), "ws://nas:5000/hubs/messages?access_token=t");
+    }
+}
@@ -0,0 +1,217 @@
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::kavita::LibraryType;
+
+/// Page turns further apart than this are treated as a break, not slow reading.
+const MAX_SAMPLE_GAP: Duration = Duration::from_secs(10 * 60);
+
+/// Samples faster than this are page skipping rather than reading.
+const MAX_PAGES_PER_MINUTE: f64 = 60.0;
+
+/// Weight of the newest sample once a library type has a few samples.
+const MIN_SAMPLE_WEIGHT: f64 = 0.1;
+
+/// Estimates are only used after this many samples.
+const MIN_SAMPLES: u32 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Rate {
+    pages_per_minute: f64,
+    samples: u32,
+}
+
+#[derive(Debug, Clone)]
+struct PageTurn {
+    chapter_id: i32,
+    page: i32,
+    at: SystemTime,
+}
+
+/// Learns how fast the user reads in each kind of library from how the
+/// current page moves between polls. Saved to disk so it carries across runs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SpeedModel {
+    rates: HashMap<String, Rate>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    #[serde(skip)]
+    last_turn: Option<PageTurn>,
+}
+
+impl SpeedModel {
+    /// Loads the model from `path`, starting empty if the file is missing or unreadable.
+    pub fn load(path: PathBuf) -> Self {
+        let mut model = match fs::read_to_string(&path) {
+            Ok(text) => match serde_json::from_str::<SpeedModel>(&text) {
+                Ok(model) => model,
+                Err(e) => {
+                    error!("Failed to parse reading speed file {}: {}", path.display(), e);
+                    SpeedModel::default()
+                },
+            },
+            Err(_) => SpeedModel::default(),
+        };
+        model.path = Some(path);
+        model
+    }
+
+    /// Learned pages per minute for this kind of library, once there are enough samples.
+    pub fn pages_per_minute(&self, library_type: LibraryType) -> Option<f64> {
+        self.rates
+            .get(library_type.name())
+            .filter(|rate| rate.samples >= MIN_SAMPLES)
+            .map(|rate| rate.pages_per_minute)
+    }
+
+    /// Records the current page. When it has moved forward since the last page
+    /// turn in the same chapter, the pace between the two updates the average.
+    pub fn observe(&mut self, library_type: LibraryType, chapter_id: i32, page: i32, now: SystemTime) {
+        let previous = match &self.last_turn {
+            Some(turn) if turn.chapter_id == chapter_id && turn.page == page => return,
+            Some(turn) if turn.chapter_id == chapter_id && turn.page < page => Some(turn.clone()),
+            _ => None,
+        };
+
+        self.last_turn = Some(PageTurn { chapter_id, page, at: now });
+
+        let Some(previous) = previous else {
+            return;
+        };
+
+        let elapsed = match now.duration_since(previous.at) {
+            Ok(elapsed) if elapsed > Duration::ZERO && elapsed <= MAX_SAMPLE_GAP => elapsed,
+            _ => return,
+        };
+
+        let sample = (page - previous.page) as f64 / (elapsed.as_secs_f64() / 60.0);
+        if sample > MAX_PAGES_PER_MINUTE {
+            return;
+        }
+
+        let rate = self.rates
+            .entry(library_type.name().to_string())
+            .or_insert(Rate { pages_per_minute: sample, samples: 0 });
+        rate.samples += 1;
+        let weight = (1.0 / rate.samples as f64).max(MIN_SAMPLE_WEIGHT);
+        rate.pages_per_minute += (sample - rate.pages_per_minute) * weight;
+
+        info!("Reading speed for {} libraries: {:.2} pages/min ({} samples)",
+              library_type.name(), rate.pages_per_minute, rate.samples);
+
+        self.save();
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        match serde_json::to_string_pretty(self) {
+            Ok(text) => {
+                if let Err(e) = fs::write(path, text) {
+                    error!("Failed to save reading speed to {}: {}", path.display(), e);
+                }
+            },
+            Err(e) => error!("Failed to serialize reading speed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    /// Reports `page` of chapter 10 in a manga library, `secs` into the run.
+    fn turn(model: &mut SpeedModel, page: i32, secs: u64) {
+        model.observe(LibraryType::Manga, 10, page, UNIX_EPOCH + Duration::from_secs(secs));
+    }
+
+    fn rate(model: &SpeedModel) -> Option<(f64, u32)> {
+        model.rates.get("manga").map(|rate| (rate.pages_per_minute, rate.samples))
+    }
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn estimates_need_a_few_samples() {
+        let mut model = SpeedModel::default();
+        for (page, secs) in [(1, 0), (2, 60), (3, 120)] {
+            turn(&mut model, page, secs);
+        }
+        assert_eq!(rate(&model).map(|(_, samples)| samples), Some(MIN_SAMPLES - 1));
+        assert_eq!(model.pages_per_minute(LibraryType::Manga), None);
+
+        turn(&mut model, 4, 180);
+        assert_eq!(model.pages_per_minute(LibraryType::Manga), Some(1.0));
+        assert_eq!(model.pages_per_minute(LibraryType::Comic), None, "rates are per library type");
+    }
+
+    #[test]
+    fn the_same_page_keeps_the_original_turn() {
+        let mut model = SpeedModel::default();
+        turn(&mut model, 1, 0);
+        turn(&mut model, 1, 300);
+        turn(&mut model, 2, 360);
+        let (pages_per_minute, samples) = rate(&model).unwrap();
+        assert_near(pages_per_minute, 1.0 / 6.0);
+        assert_eq!(samples, 1);
+    }
+
+    #[test]
+    fn going_back_or_switching_chapters_starts_over() {
+        let mut model = SpeedModel::default();
+        turn(&mut model, 10, 0);
+        turn(&mut model, 5, 60);
+        assert_eq!(rate(&model), None, "a backwards jump isn't a sample");
+
+        model.observe(LibraryType::Manga, 11, 8, UNIX_EPOCH + Duration::from_secs(90));
+        assert_eq!(rate(&model), None, "a new chapter isn't a sample");
+
+        model.observe(LibraryType::Manga, 11, 9, UNIX_EPOCH + Duration::from_secs(150));
+        assert_eq!(rate(&model), Some((1.0, 1)));
+    }
+
+    #[test]
+    fn long_breaks_are_not_samples() {
+        let mut model = SpeedModel::default();
+        turn(&mut model, 1, 0);
+        turn(&mut model, 2, MAX_SAMPLE_GAP.as_secs() + 1);
+        assert_eq!(rate(&model), None);
+
+        turn(&mut model, 3, MAX_SAMPLE_GAP.as_secs() + 61);
+        assert_eq!(rate(&model), Some((1.0, 1)), "the late turn is the next baseline");
+    }
+
+    #[test]
+    fn page_skipping_is_not_a_sample() {
+        let mut model = SpeedModel::default();
+        turn(&mut model, 1, 0);
+        turn(&mut model, 62, 60);
+        assert_eq!(rate(&model), None, "61 pages in a minute");
+
+        turn(&mut model, 122, 120);
+        assert_eq!(rate(&model), Some((MAX_PAGES_PER_MINUTE, 1)), "60 pages in a minute still counts");
+    }
+
+    #[test]
+    fn new_samples_keep_a_minimum_weight() {
+        let mut model = SpeedModel::default();
+        for minute in 0..=20 {
+            turn(&mut model, minute + 1, minute as u64 * 60);
+        }
+        assert_eq!(rate(&model), Some((1.0, 20)));
+
+        turn(&mut model, 32, 21 * 60);
+        let (pages_per_minute, samples) = rate(&model).unwrap();
+        assert_eq!(samples, 21);
+        assert_near(pages_per_minute, 1.0 + 10.0 * MIN_SAMPLE_WEIGHT);
+    }
+}
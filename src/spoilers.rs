@@ -0,0 +1,121 @@
+//! Keeps chapter titles and EPUB sections, which often give away plot points,
+//! out of the presence.
+
+use log::info;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::context::ReadingContext;
+use crate::Config;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SpoilerMode {
+    /// Show chapter titles.
+    #[default]
+    Off,
+    /// Never show chapter titles, only numbers.
+    Titles,
+    /// Only show the titles of chapters that have been read to the end.
+    Unfinished,
+}
+
+/// `spoilers` in the config. Series settings win over library settings, which
+/// win over `mode`.
+#[derive(Debug, Deserialize, Default)]
+pub struct SpoilerConfig {
+    #[serde(default)]
+    pub mode: SpoilerMode,
+    #[serde(default)]
+    pub libraries: HashMap<i32, SpoilerMode>,
+    #[serde(default)]
+    pub series: HashMap<i32, SpoilerMode>,
+}
+
+impl SpoilerConfig {
+    fn mode_for(&self, context: &ReadingContext) -> SpoilerMode {
+        self.series.get(&context.series_id)
+            .or_else(|| self.libraries.get(&context.library_id))
+            .copied()
+            .unwrap_or(self.mode)
+    }
+}
+
+/// Whether the chapter title has to be left out. A chapter that hasn't been
+/// started, like the next one Kavita moves on to, never shows its title, even
+/// with spoiler protection off.
+pub fn hides_title(config: &Config, context: &ReadingContext) -> bool {
+    let mode = config.spoilers.as_ref().map_or(SpoilerMode::Off, |spoilers| spoilers.mode_for(context));
+
+    let hide = context.page <= 0 || match mode {
+        SpoilerMode::Off => false,
+        SpoilerMode::Titles => true,
+        SpoilerMode::Unfinished => context.page < context.pages,
+    };
+
+    if hide && (context.chapter_title.is_some() || !context.section.is_empty()) {
+        if context.page <= 0 {
+            info!("Hiding the chapter title of '{}' until the chapter is started", context.series_name);
+        } else {
+            info!("Hiding the chapter title of '{}' ({:?} spoiler mode)", context.series_name, mode);
+        }
+    }
+    hide
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_config;
+    use serde_json::json;
+
+    /// Chapter 21 of series 1 in library 1, titled and on page 5 of 20.
+    fn titled() -> ReadingContext {
+        ReadingContext { chapter_title: Some("The Storm".to_string()), ..ReadingContext::sample() }
+    }
+
+    fn hides(spoilers: serde_json::Value, context: &ReadingContext) -> bool {
+        hides_title(&test_config(json!({ "spoilers": spoilers })), context)
+    }
+
+    #[test]
+    fn series_settings_win_over_library_settings_and_the_global_mode() {
+        let spoilers: SpoilerConfig = serde_json::from_value(json!({
+            "mode": "titles",
+            "libraries": { "1": "unfinished", "2": "off" },
+            "series": { "1": "off" },
+        })).unwrap();
+
+        assert_eq!(spoilers.mode_for(&titled()), SpoilerMode::Off, "series 1");
+        let other_series = ReadingContext { series_id: 5, ..titled() };
+        assert_eq!(spoilers.mode_for(&other_series), SpoilerMode::Unfinished, "library 1");
+        let other_library = ReadingContext { series_id: 5, library_id: 3, ..titled() };
+        assert_eq!(spoilers.mode_for(&other_library), SpoilerMode::Titles, "global");
+    }
+
+    #[test]
+    fn titles_mode_always_hides_the_title() {
+        let finished = ReadingContext { page: 20, ..titled() };
+        assert!(hides(json!({ "mode": "titles" }), &finished));
+        assert!(!hides(json!({ "mode": "off" }), &finished));
+        assert!(!hides_title(&test_config(json!({})), &titled()), "off without a spoilers section");
+    }
+
+    #[test]
+    fn unfinished_mode_shows_only_titles_read_to_the_end() {
+        let spoilers = json!({ "mode": "unfinished" });
+        assert!(hides(spoilers.clone(), &titled()));
+        assert!(!hides(spoilers.clone(), &ReadingContext { page: 20, ..titled() }));
+        assert!(hides(spoilers, &ReadingContext { page: 0, pages: 0, ..titled() }), "not started");
+    }
+
+    #[test]
+    fn unstarted_chapters_hide_their_title_in_every_mode() {
+        let unstarted = ReadingContext { page: 0, ..titled() };
+        for mode in ["off", "titles", "unfinished"] {
+            assert!(hides(json!({ "mode": mode }), &unstarted), "{}", mode);
+        }
+        assert!(hides(json!({ "series": { "1": "off" } }), &unstarted), "series setting");
+        assert!(hides_title(&test_config(json!({})), &unstarted), "without a spoilers section");
+    }
+}
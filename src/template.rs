@@ -0,0 +1,358 @@
+//! Presence text templates.
+//!
+//! `{name}` is replaced by the value of a placeholder. Text inside `[...]` is
+//! only kept when every placeholder in it has a value, so `[Ch. {chapter}]`
+//! disappears entirely for a book. Outside brackets, a missing value also takes
+//! the text since the previous value with it (e.g. ` - ` or ` of `), so
+//! `{author} - {chapter}` renders as just the author when there is no chapter.
+//! A backslash escapes the next character.
+
+use unicode_segmentation::UnicodeSegmentation;
+
+const ELLIPSIS: char = '…';
+
+#[derive(Debug)]
+enum Token {
+    Literal(String),
+    Placeholder(String),
+    Optional(Vec<Token>),
+}
+
+/// Renders `template`, asking `lookup` for each placeholder. `None` or an empty
+/// string counts as a missing value.
+pub fn render(template: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+    let tokens = parse(&mut template.chars(), false);
+    let pieces = render_pieces(&tokens, lookup, false).unwrap_or_default();
+    let text: String = pieces.pieces.iter().map(|p| p.text.as_str()).collect();
+    text.trim().to_string()
+}
+
+/// Every placeholder name in `template`, including those inside brackets.
+pub fn placeholders(template: &str) -> Vec<String> {
+    fn collect(tokens: &[Token], out: &mut Vec<String>) {
+        for token in tokens {
+            match token {
+                Token::Placeholder(name) => out.push(name.clone()),
+                Token::Optional(inner) => collect(inner, out),
+                Token::Literal(_) => {}
+            }
+        }
+    }
+
+    let mut names = Vec::new();
+    collect(&parse(&mut template.chars(), false), &mut names);
+    names
+}
+
+/// Names used in `template` that are not in `known`.
+pub fn unknown_placeholders(template: &str, known: &[&str]) -> Vec<String> {
+    placeholders(template)
+        .into_iter()
+        .filter(|name| !known.contains(&name.as_str()))
+        .collect()
+}
+
+/// Shortens `text` to at most `max_chars` characters, cutting on a grapheme
+/// boundary and ending with an ellipsis when anything was removed.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+
+    let mut out = String::new();
+    let mut count = 0;
+    for grapheme in text.graphemes(true) {
+        let len = grapheme.chars().count();
+        if count + len + 1 > max_chars {
+            break;
+        }
+        out.push_str(grapheme);
+        count += len;
+    }
+
+    let mut out = out.trim_end().to_string();
+    out.push(ELLIPSIS);
+    out
+}
+
+fn parse(chars: &mut std::str::Chars, in_optional: bool) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    literal.push(escaped);
+                }
+            },
+            '{' => {
+                let mut name = String::new();
+                let mut closed = false;
+                for n in chars.by_ref() {
+                    if n == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(n);
+                }
+                if closed {
+                    flush(&mut literal, &mut tokens);
+                    tokens.push(Token::Placeholder(name.trim().to_string()));
+                } else {
+                    literal.push('{');
+                    literal.push_str(&name);
+                }
+            },
+            '[' => {
+                flush(&mut literal, &mut tokens);
+                tokens.push(Token::Optional(parse(chars, true)));
+            },
+            ']' if in_optional => {
+                flush(&mut literal, &mut tokens);
+                return tokens;
+            },
+            _ => literal.push(c),
+        }
+    }
+
+    flush(&mut literal, &mut tokens);
+    tokens
+}
+
+fn flush(literal: &mut String, tokens: &mut Vec<Token>) {
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(std::mem::take(literal)));
+    }
+}
+
+struct Piece {
+    text: String,
+    kind: PieceKind,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PieceKind {
+    /// A placeholder's value.
+    Value,
+    /// Words from the template, such as `Ch` or `of`.
+    Label,
+    /// Spaces and punctuation only, such as ` - ` or `, `.
+    Separator,
+}
+
+/// What to remove from the separator that follows a missing value.
+#[derive(Default, Clone, Copy, PartialEq)]
+enum Drop {
+    #[default]
+    Nothing,
+    /// The value was the first thing in the text, so the whole separator goes.
+    Separator,
+    /// The separator before the value opened a bracket; only its closing half goes.
+    ClosingBracket,
+}
+
+#[derive(Default)]
+struct Pieces {
+    pieces: Vec<Piece>,
+    drop_next: Drop,
+}
+
+impl Pieces {
+    fn push(&mut self, mut piece: Piece) {
+        match (piece.kind, self.drop_next) {
+            // A bracket stays open over the values inside it, so only its closing half ends the drop.
+            (PieceKind::Separator, Drop::ClosingBracket) => {
+                let text = piece.text.trim_start_matches([')', ']', '}']);
+                if text.len() < piece.text.len() {
+                    self.drop_next = Drop::Nothing;
+                    piece.text = text.to_string();
+                    if piece.text.is_empty() {
+                        return;
+                    }
+                }
+            },
+            (PieceKind::Separator, Drop::Separator) => return,
+            (PieceKind::Separator, Drop::Nothing) | (_, Drop::ClosingBracket) => {},
+            _ => self.drop_next = Drop::Nothing,
+        }
+        self.pieces.push(piece);
+    }
+
+    /// Splits a literal so its leading and trailing punctuation can be dropped
+    /// on their own: `" - Ch. "` becomes `" - "`, `"Ch"` and `". "`.
+    fn push_literal(&mut self, text: &str) {
+        let start = text.find(char::is_alphanumeric);
+        let end = text.rfind(char::is_alphanumeric)
+            .map(|i| i + text[i..].chars().next().map_or(1, char::len_utf8));
+
+        match (start, end) {
+            (Some(start), Some(end)) => {
+                let parts = [
+                    (&text[..start], PieceKind::Separator),
+                    (&text[start..end], PieceKind::Label),
+                    (&text[end..], PieceKind::Separator),
+                ];
+                for (part, kind) in parts {
+                    if !part.is_empty() {
+                        self.push(Piece { text: part.to_string(), kind });
+                    }
+                }
+            },
+            _ => self.push(Piece { text: text.to_string(), kind: PieceKind::Separator }),
+        }
+    }
+
+    /// Removes the text that led up to a missing value from the previous one,
+    /// such as ` - ` or ` of `. If that text opened a bracket, the closing one
+    /// after the value goes as well. Without a previous value, everything so
+    /// far goes along with the separator that follows.
+    fn missing(&mut self) {
+        match self.pieces.iter().rposition(|p| p.kind == PieceKind::Value) {
+            Some(last_value) => {
+                if self.pieces.drain(last_value + 1..).any(|popped| popped.text.contains(['(', '[', '{'])) {
+                    self.drop_next = Drop::ClosingBracket;
+                }
+            },
+            None => {
+                self.pieces.clear();
+                self.drop_next = Drop::Separator;
+            }
+        }
+    }
+
+    fn has_text(&self) -> bool {
+        self.pieces.iter().any(|p| p.kind != PieceKind::Separator)
+    }
+}
+
+/// Renders `tokens` into pieces. Inside an optional section (`strict`) a missing
+/// placeholder discards the whole section by returning `None`; elsewhere it only
+/// removes the separator next to it.
+fn render_pieces(
+    tokens: &[Token],
+    lookup: &dyn Fn(&str) -> Option<String>,
+    strict: bool,
+) -> Option<Pieces> {
+    let mut pieces = Pieces::default();
+
+    for token in tokens {
+        match token {
+            Token::Literal(text) => pieces.push_literal(text),
+            Token::Placeholder(name) => match lookup(name).filter(|v| !v.is_empty()) {
+                Some(text) => pieces.push(Piece { text, kind: PieceKind::Value }),
+                None if strict => return None,
+                None => pieces.missing(),
+            },
+            Token::Optional(inner) => match render_pieces(inner, lookup, true).filter(Pieces::has_text) {
+                Some(section) => section.pieces.into_iter().for_each(|piece| pieces.push(piece)),
+                None => pieces.missing(),
+            },
+        }
+    }
+
+    Some(pieces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders with `series`, `author`, `volume`, `chapter`, `page` and `pages` set, except `missing`.
+    fn render_with(template: &str, missing: &[&str]) -> String {
+        let lookup = |name: &str| {
+            let value = match name {
+                "series" => "Series",
+                "author" => "Author",
+                "volume" => "3",
+                "chapter" => "21",
+                "page" => "5",
+                "pages" => "20",
+                _ => return None,
+            };
+            (!missing.contains(&name)).then(|| value.to_string())
+        };
+        render(template, &lookup)
+    }
+
+    #[test]
+    fn render_fills_in_placeholders() {
+        assert_eq!(render_with("{series} by {author}", &[]), "Series by Author");
+        assert_eq!(render_with("{ series }", &[]), "Series");
+    }
+
+    #[test]
+    fn render_drops_the_separator_after_a_missing_leading_value() {
+        assert_eq!(render_with("{author} - {chapter}", &["author"]), "21");
+        assert_eq!(render_with("{author} - Ch. {chapter}", &["author"]), "Ch. 21");
+        assert_eq!(render_with("Ch. {chapter} - {series}", &["chapter"]), "Series");
+    }
+
+    #[test]
+    fn render_drops_the_text_before_a_missing_trailing_value() {
+        assert_eq!(render_with("{author} - {chapter}", &["chapter"]), "Author");
+        assert_eq!(render_with("{page} of {pages}", &["pages"]), "5");
+        assert_eq!(render_with("Page {page} of {pages}", &["pages"]), "Page 5");
+        assert_eq!(render_with("{series} - Ch. {chapter}", &["chapter"]), "Series");
+    }
+
+    #[test]
+    fn render_drops_brackets_around_a_missing_value() {
+        assert_eq!(render_with("{series} ({volume}) - {chapter}", &["volume"]), "Series - 21");
+        assert_eq!(render_with("{series} (Vol. {volume})", &["volume"]), "Series");
+        assert_eq!(render_with("{series} ({volume})", &[]), "Series (3)");
+        assert_eq!(render_with("{series} ({volume}, {chapter})", &["volume"]), "Series, 21");
+        assert_eq!(render_with("{series} ({volume}, {chapter}) - {page}", &["volume"]), "Series, 21 - 5");
+    }
+
+    #[test]
+    fn render_keeps_optional_sections_only_when_complete() {
+        assert_eq!(render_with("{series}[ - Vol. {volume}]", &[]), "Series - Vol. 3");
+        assert_eq!(render_with("{series}[ - Vol. {volume}]", &["volume"]), "Series");
+        assert_eq!(render_with("{series}[ - Page {page} of {pages}]", &["pages"]), "Series");
+        assert_eq!(render_with("[{author} - ]{series}", &["author"]), "Series");
+    }
+
+    #[test]
+    fn render_reads_escapes_as_literals() {
+        assert_eq!(render_with("\\[{series}\\]", &[]), "[Series]");
+        assert_eq!(render_with("\\{series\\}", &[]), "{series}");
+    }
+
+    #[test]
+    fn render_keeps_an_unclosed_brace_as_text() {
+        assert_eq!(render_with("{series} {chapter", &[]), "Series {chapter");
+    }
+
+    #[test]
+    fn placeholders_include_those_in_optional_sections() {
+        assert_eq!(placeholders("{series}[ - {volume}[ {chapter}]]"), ["series", "volume", "chapter"]);
+        let known = ["series", "volume"];
+        assert_eq!(unknown_placeholders("{series} {nope}[ - {volume}[ {maybe}]]", &known), ["nope", "maybe"]);
+    }
+
+    #[test]
+    fn truncate_leaves_short_text_alone() {
+        let text = "a".repeat(128);
+        assert_eq!(truncate(&text, 128), text);
+    }
+
+    #[test]
+    fn truncate_does_not_split_zwj_emoji() {
+        // The family emoji is five chars joined into one grapheme.
+        let text = format!("{}{}", "a".repeat(124), "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}");
+        assert_eq!(text.chars().count(), 129);
+
+        let truncated = truncate(&text, 128);
+        assert_eq!(truncated, format!("{}…", "a".repeat(124)));
+        assert!(truncated.chars().count() <= 128);
+    }
+
+    #[test]
+    fn truncate_keeps_combining_marks_with_their_letter() {
+        let text = "e\u{301}".repeat(65);
+        let truncated = truncate(&text, 128);
+        assert_eq!(truncated, format!("{}…", "e\u{301}".repeat(63)));
+        assert!(truncated.chars().count() <= 128);
+    }
+}